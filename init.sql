@@ -32,8 +32,10 @@ CREATE TABLE IF NOT EXISTS subscription (
 );
 
 CREATE TABLE IF NOT EXISTS account_value (
-  account_id integer REFERENCES account(account_id) UNIQUE,
-  account_value numeric
+  account_value_id SERIAL PRIMARY KEY,
+  account_id integer REFERENCES account(account_id),
+  account_value numeric,
+  recorded_at timestamp NOT NULL DEFAULT clock_timestamp()
 );
 
 CREATE TABLE IF NOT EXISTS portfolio (
@@ -42,3 +44,163 @@ CREATE TABLE IF NOT EXISTS portfolio (
     item varchar(50),
     value numeric
 );
+
+CREATE TABLE IF NOT EXISTS budget (
+    budget_id SERIAL PRIMARY KEY,
+    category_id integer REFERENCES expense_category (category_id) UNIQUE,
+    period varchar(10) NOT NULL DEFAULT 'month',
+    amount numeric NOT NULL,
+    rollover boolean NOT NULL DEFAULT false
+);
+
+-- rollover carries what is left over from every period since start_date
+ALTER TABLE budget
+    ADD COLUMN IF NOT EXISTS start_date date NOT NULL DEFAULT CURRENT_DATE;
+
+ALTER TABLE expense_category
+    ADD COLUMN IF NOT EXISTS parent_id integer REFERENCES expense_category (category_id);
+
+CREATE TABLE IF NOT EXISTS income (
+    income_id SERIAL PRIMARY KEY,
+    date date,
+    account_id integer REFERENCES account (account_id),
+    amount numeric,
+    source varchar(50),
+    note varchar(140)
+);
+
+CREATE TABLE IF NOT EXISTS transfer (
+    transfer_id SERIAL PRIMARY KEY,
+    date date,
+    from_account_id integer REFERENCES account (account_id),
+    to_account_id integer REFERENCES account (account_id),
+    amount numeric,
+    note varchar(140)
+);
+
+ALTER TABLE account
+    ADD COLUMN IF NOT EXISTS track_balance boolean NOT NULL DEFAULT false,
+    ADD COLUMN IF NOT EXISTS opening_balance numeric NOT NULL DEFAULT 0,
+    ADD COLUMN IF NOT EXISTS opening_date date;
+
+-- account_value used to hold a single overwritten row per account. Run
+-- once: the value kept then is recorded at '-infinity', since it is not
+-- known when it was set, so it counts as the balance on any earlier date.
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM information_schema.columns
+                   WHERE table_name = 'account_value' AND column_name = 'recorded_at') THEN
+        ALTER TABLE account_value
+            DROP CONSTRAINT IF EXISTS account_value_account_id_key,
+            ADD COLUMN IF NOT EXISTS account_value_id SERIAL,
+            ADD COLUMN recorded_at timestamp NOT NULL DEFAULT '-infinity';
+        ALTER TABLE account_value ALTER COLUMN recorded_at SET DEFAULT clock_timestamp();
+    END IF;
+END $$;
+
+-- subscription_price used to always be a yearly price
+ALTER TABLE subscription
+    ADD COLUMN IF NOT EXISTS billing_interval varchar(10) NOT NULL DEFAULT 'year',
+    ADD COLUMN IF NOT EXISTS start_date date;
+
+ALTER TABLE subscription
+    ADD COLUMN IF NOT EXISTS account_id integer REFERENCES account (account_id);
+
+-- Renewals already turned into expenses, so posting never repeats one
+CREATE TABLE IF NOT EXISTS subscription_posting (
+    subscription_id integer REFERENCES subscription (subscription_id),
+    renewal_date date,
+    expense_id integer REFERENCES expense (expense_id) ON DELETE SET NULL,
+    PRIMARY KEY (subscription_id, renewal_date)
+);
+
+-- Dates `subscription post` ran, so the next run starts where it left off
+CREATE TABLE IF NOT EXISTS subscription_post_run (
+    ran_on date NOT NULL
+);
+
+-- status is active, paused or cancelled, changed on status_date
+ALTER TABLE subscription
+    ADD COLUMN IF NOT EXISTS status varchar(10) NOT NULL DEFAULT 'active',
+    ADD COLUMN IF NOT EXISTS status_date date;
+
+-- Every pause, resume and cancellation, so posting only skips the time a
+-- subscription was stopped
+CREATE TABLE IF NOT EXISTS subscription_status_change (
+    status_change_id SERIAL PRIMARY KEY,
+    subscription_id integer REFERENCES subscription (subscription_id),
+    changed_on date NOT NULL,
+    status varchar(10) NOT NULL
+);
+
+-- start the history of subscriptions changed before it was kept
+INSERT INTO subscription_status_change (subscription_id, changed_on, status)
+SELECT subscription_id, status_date, status
+FROM subscription
+WHERE status_date IS NOT NULL
+  AND NOT EXISTS (SELECT 1 FROM subscription_status_change
+                  WHERE subscription_status_change.subscription_id = subscription.subscription_id);
+
+CREATE TABLE IF NOT EXISTS subscription_price_change (
+    price_change_id SERIAL PRIMARY KEY,
+    subscription_id integer REFERENCES subscription (subscription_id),
+    changed_on date NOT NULL,
+    old_price numeric NOT NULL,
+    old_billing_interval varchar(10) NOT NULL,
+    new_price numeric NOT NULL
+);
+
+ALTER TABLE portfolio
+    ADD COLUMN IF NOT EXISTS quantity numeric,
+    ADD COLUMN IF NOT EXISTS price numeric,
+    ADD COLUMN IF NOT EXISTS cost_basis numeric;
+
+-- an item appears once per snapshot; run once, before the index exists,
+-- merging rows of an item recorded more than once into a single row
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM pg_indexes
+                   WHERE tablename = 'portfolio' AND indexname = 'portfolio_date_item') THEN
+        WITH merged AS (
+            DELETE FROM portfolio
+            WHERE (date, item) IN (SELECT date, item FROM portfolio
+                                   GROUP BY date, item HAVING count(*) > 1)
+            RETURNING date, item, value, quantity, cost_basis
+        )
+        INSERT INTO portfolio (date, item, value, quantity, price, cost_basis)
+        SELECT date, item, total.value, total.quantity,
+               total.value / NULLIF(total.quantity, 0), total.cost_basis
+        FROM (SELECT date, item, SUM(value) AS value,
+                     CASE WHEN count(quantity) = count(*) THEN SUM(quantity) END AS quantity,
+                     CASE WHEN count(cost_basis) = count(*) THEN SUM(cost_basis) END AS cost_basis
+              FROM merged
+              GROUP BY date, item) total;
+        CREATE UNIQUE INDEX portfolio_date_item ON portfolio (date, item);
+    END IF;
+END $$;
+
+-- target is NULL until one is set with `portfolio target`
+CREATE TABLE IF NOT EXISTS asset_class (
+    asset_class_id SERIAL PRIMARY KEY,
+    name varchar(50) UNIQUE NOT NULL,
+    target numeric
+);
+
+-- classes used to default to a target of 0; run once, while target is
+-- still NOT NULL, so targets set to 0 later are kept
+DO $$
+BEGIN
+    IF EXISTS (SELECT 1 FROM information_schema.columns
+               WHERE table_name = 'asset_class' AND column_name = 'target'
+                 AND is_nullable = 'NO') THEN
+        ALTER TABLE asset_class
+            ALTER COLUMN target DROP NOT NULL,
+            ALTER COLUMN target DROP DEFAULT;
+        UPDATE asset_class SET target = NULL WHERE target = 0;
+    END IF;
+END $$;
+
+CREATE TABLE IF NOT EXISTS portfolio_item_class (
+    item varchar(50) PRIMARY KEY,
+    asset_class_id integer NOT NULL REFERENCES asset_class (asset_class_id)
+);
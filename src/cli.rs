@@ -0,0 +1,657 @@
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use structopt::StructOpt;
+
+use crate::csv::ImportMode;
+use crate::datetime::{self, BillingInterval, Period};
+
+#[derive(StructOpt)]
+#[structopt(name = "haibun", about = "Manage your finances from the command line")]
+pub enum Opts {
+    /// Record and view expenses
+    Expense(ExpenseCmd),
+
+    /// Record and view subscriptions (lists active ones without a subcommand)
+    Subscription {
+        #[structopt(subcommand)]
+        cmd: Option<SubscriptionCmd>,
+    },
+
+    /// Manage accounts and their values
+    Account(AccountCmd),
+
+    /// Import and view portfolio snapshots
+    Portfolio(PortfolioCmd),
+
+    /// Set and track spending budgets per expense category
+    Budget(BudgetCmd),
+
+    /// Manage expense categories
+    Category(CategoryCmd),
+
+    /// Record and view income
+    Income(IncomeCmd),
+
+    /// Show income, expenses and net cash flow per month, week or year
+    Cashflow(CashflowOpts),
+
+    /// Show assets, liabilities and net worth across accounts and the portfolio
+    Networth(NetworthOpts),
+}
+
+#[derive(StructOpt)]
+pub enum ExpenseCmd {
+    /// View recent expenses
+    View(ExpenseViewOpts),
+
+    /// Add a new expense, prompting for any field not given as a flag
+    Add(ExpenseFields),
+
+    /// Edit an expense, prompting with its current values unless flags are given
+    Edit(ExpenseEditOpts),
+
+    /// Delete an expense
+    Delete(ExpenseDeleteOpts),
+
+    /// Show total spending per category per month, week or year
+    Report(ExpenseReportOpts),
+}
+
+#[derive(StructOpt, Debug)]
+pub struct ExpenseViewOpts {
+    /// Number of expenses to display (default 10 when no filter is given, otherwise all)
+    #[structopt(short, long)]
+    pub number: Option<i64>,
+
+    #[structopt(flatten)]
+    pub filter: ExpenseFilterOpts,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct ExpenseReportOpts {
+    /// Group expenses by week, month or year
+    #[structopt(short, long, default_value = "month")]
+    pub by: Period,
+
+    #[structopt(flatten)]
+    pub filter: ExpenseFilterOpts,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct ExpenseFilterOpts {
+    /// Only show expenses in this category (name or id)
+    #[structopt(short, long)]
+    pub category: Option<String>,
+
+    #[structopt(flatten)]
+    pub range: DateRangeOpts,
+
+    /// Only show expenses paid from this account (name or id)
+    #[structopt(short, long)]
+    pub account: Option<String>,
+
+    /// Only show expenses of at least this amount
+    #[structopt(long)]
+    pub min: Option<Decimal>,
+
+    /// Only show expenses of at most this amount
+    #[structopt(long)]
+    pub max: Option<Decimal>,
+
+    /// Only show expenses whose note contains this text
+    #[structopt(short, long)]
+    pub search: Option<String>,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct ExpenseFields {
+    /// Date of the expense (YYYY-MM-DD)
+    #[structopt(short, long, parse(try_from_str = datetime::parse_date))]
+    pub date: Option<NaiveDate>,
+
+    /// Account name or id
+    #[structopt(short, long)]
+    pub account: Option<String>,
+
+    /// Amount spent
+    #[structopt(short = "m", long)]
+    pub amount: Option<Decimal>,
+
+    /// Category name or id
+    #[structopt(short, long)]
+    pub category: Option<String>,
+
+    /// Note
+    #[structopt(short, long)]
+    pub note: Option<String>,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct ExpenseEditOpts {
+    /// Id of the expense to edit
+    pub id: i32,
+
+    #[structopt(flatten)]
+    pub fields: ExpenseFields,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct ExpenseDeleteOpts {
+    /// Id of the expense to delete
+    pub id: i32,
+
+    /// Delete without asking for confirmation
+    #[structopt(short, long)]
+    pub yes: bool,
+}
+
+#[derive(StructOpt)]
+pub enum SubscriptionCmd {
+    /// View active subscriptions with yearly and monthly totals
+    View(SubscriptionViewOpts),
+
+    /// Add a new subscription, prompting for any field not given as a flag
+    Add(SubscriptionFields),
+
+    /// List subscription renewals due soon
+    Upcoming(UpcomingOpts),
+
+    /// Export renewals of active subscriptions as an iCalendar file
+    Ical(IcalOpts),
+
+    /// Record renewals due since the last run (or today on the first run) as
+    /// expenses, skipping ones already posted
+    Post(PostOpts),
+
+    /// Edit a subscription, prompting with its current values unless flags are given
+    Edit(SubscriptionEditOpts),
+
+    /// Stop a subscription's renewals until it is resumed
+    Pause(SubscriptionStatusOpts),
+
+    /// Restart renewals of a paused or cancelled subscription
+    Resume(SubscriptionStatusOpts),
+
+    /// End a subscription, keeping it in the history
+    Cancel(SubscriptionStatusOpts),
+
+    /// Delete a subscription and its price history
+    Delete(SubscriptionDeleteOpts),
+}
+
+#[derive(StructOpt, Debug)]
+pub struct SubscriptionViewOpts {
+    /// Include paused and cancelled subscriptions (not counted in the totals)
+    #[structopt(short, long)]
+    pub all: bool,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct IcalOpts {
+    /// File to write, e.g. renewals.ics (defaults to standard output)
+    #[structopt(short, long)]
+    pub output: Option<String>,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct SubscriptionEditOpts {
+    /// Subscription name or id
+    pub subscription: String,
+
+    #[structopt(flatten)]
+    pub fields: SubscriptionFields,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct SubscriptionStatusOpts {
+    /// Subscription name or id
+    pub subscription: String,
+
+    /// Date the change takes effect (YYYY-MM-DD, defaults to today)
+    #[structopt(short, long, parse(try_from_str = datetime::parse_date))]
+    pub date: Option<NaiveDate>,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct SubscriptionDeleteOpts {
+    /// Subscription name or id
+    pub subscription: String,
+
+    /// Delete without asking for confirmation
+    #[structopt(short, long)]
+    pub yes: bool,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct SubscriptionFields {
+    /// Name of the subscription
+    #[structopt(short, long)]
+    pub name: Option<String>,
+
+    /// Category name or id
+    #[structopt(short, long)]
+    pub category: Option<String>,
+
+    /// Price charged each billing interval
+    #[structopt(short, long)]
+    pub price: Option<Decimal>,
+
+    /// Billing interval: week, month, quarter or year
+    #[structopt(short, long)]
+    pub every: Option<BillingInterval>,
+
+    /// Date of the first charge, from which renewals are counted (YYYY-MM-DD)
+    #[structopt(short, long, parse(try_from_str = datetime::parse_date))]
+    pub start: Option<NaiveDate>,
+
+    /// Account name or id renewals are paid from, needed to post them as expenses
+    #[structopt(short, long)]
+    pub account: Option<String>,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct PostOpts {
+    /// Post renewals from this date instead of the last run (YYYY-MM-DD)
+    #[structopt(short, long, parse(try_from_str = datetime::parse_date))]
+    pub since: Option<NaiveDate>,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct UpcomingOpts {
+    /// Number of days ahead to look
+    #[structopt(short, long, default_value = "30")]
+    pub days: i64,
+}
+
+#[derive(StructOpt)]
+pub enum AccountCmd {
+    /// View the current value of each account
+    View(AccountViewOpts),
+
+    /// View how the value of an account changed over time
+    History {
+        /// Account name or id
+        account: String,
+    },
+
+    /// Add a new account
+    Add,
+
+    /// Update the value of an account
+    Manage,
+
+    /// Move money from one account to another, updating both values
+    Transfer(TransferOpts),
+
+    /// View transfer history, optionally for one account
+    Transfers(TransfersOpts),
+
+    /// Keep an account's value in step with its expenses, income and transfers
+    Track(TrackOpts),
+
+    /// Rebuild tracked account values from their opening balance and transactions
+    Recompute {
+        /// Only recompute this account (name or id)
+        account: Option<String>,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+pub struct TrackOpts {
+    /// Account name or id
+    pub account: String,
+
+    /// Stop tracking the account's balance
+    #[structopt(long)]
+    pub off: bool,
+
+    /// Balance at the start of the opening date (defaults to the current value)
+    #[structopt(short = "b", long)]
+    pub opening_balance: Option<Decimal>,
+
+    /// Transactions before this date are not counted (defaults to today)
+    #[structopt(short = "d", long, parse(try_from_str = datetime::parse_date))]
+    pub opening_date: Option<NaiveDate>,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct AccountViewOpts {
+    /// Show values as of the end of this date (YYYY-MM-DD)
+    #[structopt(short, long, parse(try_from_str = datetime::parse_date))]
+    pub date: Option<NaiveDate>,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct TransferOpts {
+    /// Date of the transfer (YYYY-MM-DD)
+    #[structopt(short, long, parse(try_from_str = datetime::parse_date))]
+    pub date: Option<NaiveDate>,
+
+    /// Account name or id to take the money from
+    #[structopt(short, long)]
+    pub from: Option<String>,
+
+    /// Account name or id to put the money into
+    #[structopt(short, long)]
+    pub to: Option<String>,
+
+    /// Amount transferred
+    #[structopt(short = "m", long)]
+    pub amount: Option<Decimal>,
+
+    /// Note
+    #[structopt(short, long)]
+    pub note: Option<String>,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct TransfersOpts {
+    /// Only show transfers to or from this account (name or id)
+    pub account: Option<String>,
+
+    /// Number of transfers to display
+    #[structopt(short, long)]
+    pub number: Option<i64>,
+}
+
+#[derive(StructOpt)]
+pub enum PortfolioCmd {
+    /// View the latest portfolio snapshot
+    View(PortfolioViewOpts),
+
+    /// List the dates of every portfolio snapshot
+    Dates,
+
+    /// Compare two portfolio snapshots item by item
+    Diff(PortfolioDiffOpts),
+
+    /// Put a portfolio item in an asset class
+    Class {
+        /// Item name as it appears in the imported csv
+        item: String,
+
+        /// Asset class, e.g. Stocks or Bonds
+        class: String,
+    },
+
+    /// Set the share of the portfolio an asset class should make up
+    Target {
+        /// Asset class
+        class: String,
+
+        /// Target percentage of the portfolio
+        percent: Decimal,
+    },
+
+    /// Show drift from the target allocation and the trades needed to rebalance
+    Rebalance(RebalanceOpts),
+
+    /// Import a portfolio snapshot from a CSV file
+    #[structopt(alias = "import")]
+    Add(PortfolioAddOpts),
+
+    /// Correct or remove an item of a snapshot, prompting unless flags are given
+    Manage(PortfolioManageOpts),
+}
+
+#[derive(StructOpt, Debug)]
+pub struct PortfolioManageOpts {
+    /// Change the latest snapshot taken on or before this date (YYYY-MM-DD)
+    #[structopt(short, long, parse(try_from_str = datetime::parse_date))]
+    pub date: Option<NaiveDate>,
+
+    /// ID of the item, as shown by `portfolio view`
+    #[structopt(short, long)]
+    pub id: Option<i32>,
+
+    /// New value of the item; 0 removes it from the snapshot
+    #[structopt(short, long)]
+    pub value: Option<Decimal>,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct PortfolioViewOpts {
+    /// Show the latest snapshot taken on or before this date (YYYY-MM-DD)
+    #[structopt(short, long, parse(try_from_str = datetime::parse_date))]
+    pub date: Option<NaiveDate>,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct PortfolioDiffOpts {
+    /// Date of the earlier snapshot, or the latest one before it (YYYY-MM-DD)
+    #[structopt(parse(try_from_str = datetime::parse_date))]
+    pub from: NaiveDate,
+
+    /// Date of the later snapshot, or the latest one before it (YYYY-MM-DD)
+    #[structopt(parse(try_from_str = datetime::parse_date))]
+    pub to: NaiveDate,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct RebalanceOpts {
+    /// New money to invest, split so the allocation moves toward its targets
+    #[structopt(short, long)]
+    pub contribution: Option<Decimal>,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct PortfolioAddOpts {
+    /// CSV file to read, named with the snapshot date (e.g. 2021-03-14.csv)
+    #[structopt(short, long)]
+    pub file: String,
+
+    /// Named [csv.<profile>] section of the config describing the file's layout
+    /// (defaults to the profile whose filename pattern matches, or [csv])
+    #[structopt(short, long)]
+    pub profile: Option<String>,
+
+    /// What to do with items already in the snapshot: skip them, overwrite
+    /// changed values, or replace the whole snapshot with the file
+    #[structopt(short, long, default_value = "skip")]
+    pub mode: ImportMode,
+
+    /// Show what would be imported without writing anything
+    #[structopt(long)]
+    pub dry_run: bool,
+}
+
+#[derive(StructOpt)]
+pub enum BudgetCmd {
+    /// Set the budget of an expense category
+    Set(BudgetSetOpts),
+
+    /// View spending against each budget
+    View(BudgetViewOpts),
+}
+
+#[derive(StructOpt, Debug)]
+pub struct BudgetSetOpts {
+    /// Category name or id
+    pub category: String,
+
+    /// Amount that can be spent each period
+    pub amount: Decimal,
+
+    /// Length of the budget period: week, month or year
+    #[structopt(short, long, default_value = "month")]
+    pub period: Period,
+
+    /// Carry what is left (or overspent) in one period into the next
+    #[structopt(short, long)]
+    pub rollover: bool,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct BudgetViewOpts {
+    /// Show the budget periods containing this date instead of today (YYYY-MM-DD)
+    #[structopt(short, long, parse(try_from_str = datetime::parse_date))]
+    pub date: Option<NaiveDate>,
+}
+
+#[derive(StructOpt)]
+pub enum CategoryCmd {
+    /// List expense categories
+    View,
+
+    /// Add a new category
+    Add {
+        /// Name of the category
+        name: String,
+
+        /// Make the new category a subcategory of this one (name or id)
+        #[structopt(short, long)]
+        parent: Option<String>,
+    },
+
+    /// Move a category under another, or to the top level if no parent is given
+    Move {
+        /// Category name or id
+        category: String,
+
+        /// New parent category (name or id)
+        #[structopt(short, long)]
+        parent: Option<String>,
+    },
+
+    /// Rename a category
+    Rename {
+        /// Category name or id
+        category: String,
+
+        /// New name
+        name: String,
+    },
+
+    /// Move all expenses, subscriptions and subcategories of a category into another and delete it
+    Merge(CategoryMergeOpts),
+
+    /// Delete a category that is no longer used
+    Delete(CategoryDeleteOpts),
+}
+
+#[derive(StructOpt, Debug)]
+pub struct CategoryMergeOpts {
+    /// Category to merge and delete (name or id)
+    pub from: String,
+
+    /// Category to merge into (name or id)
+    pub into: String,
+
+    /// Merge without asking for confirmation
+    #[structopt(short, long)]
+    pub yes: bool,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct CategoryDeleteOpts {
+    /// Category name or id
+    pub category: String,
+
+    /// Move expenses, subscriptions and subcategories still using the category here first
+    #[structopt(short, long)]
+    pub reassign: Option<String>,
+
+    /// Delete without asking for confirmation
+    #[structopt(short, long)]
+    pub yes: bool,
+}
+
+#[derive(StructOpt)]
+pub enum IncomeCmd {
+    /// View recent income
+    View(IncomeViewOpts),
+
+    /// Add a new income, prompting for any field not given as a flag
+    Add(IncomeFields),
+}
+
+#[derive(StructOpt, Debug)]
+pub struct IncomeViewOpts {
+    /// Number of incomes to display (default 10 when no filter is given, otherwise all)
+    #[structopt(short, long)]
+    pub number: Option<i64>,
+
+    #[structopt(flatten)]
+    pub range: DateRangeOpts,
+
+    /// Only show income paid into this account (name or id)
+    #[structopt(short, long)]
+    pub account: Option<String>,
+
+    /// Only show income from this source
+    #[structopt(short, long)]
+    pub source: Option<String>,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct IncomeFields {
+    /// Date of the income (YYYY-MM-DD)
+    #[structopt(short, long, parse(try_from_str = datetime::parse_date))]
+    pub date: Option<NaiveDate>,
+
+    /// Account name or id the income was paid into
+    #[structopt(short, long)]
+    pub account: Option<String>,
+
+    /// Amount received
+    #[structopt(short = "m", long)]
+    pub amount: Option<Decimal>,
+
+    /// Where the income came from, e.g. an employer
+    #[structopt(short, long)]
+    pub source: Option<String>,
+
+    /// Note
+    #[structopt(short, long)]
+    pub note: Option<String>,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct CashflowOpts {
+    /// Group by week, month or year
+    #[structopt(short, long, default_value = "month")]
+    pub by: Period,
+
+    #[structopt(flatten)]
+    pub range: DateRangeOpts,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct NetworthOpts {
+    /// Show net worth at the end of each of the last N months instead of a breakdown
+    #[structopt(short, long)]
+    pub months: Option<u32>,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct DateRangeOpts {
+    /// Only include dates on or after this one (YYYY-MM-DD)
+    #[structopt(long, parse(try_from_str = datetime::parse_date), conflicts_with_all = &["month", "year"])]
+    pub from: Option<NaiveDate>,
+
+    /// Only include dates on or before this one (YYYY-MM-DD)
+    #[structopt(long, parse(try_from_str = datetime::parse_date), conflicts_with_all = &["month", "year"])]
+    pub to: Option<NaiveDate>,
+
+    /// Only include this month (YYYY-MM)
+    #[structopt(long, parse(try_from_str = datetime::month_range), conflicts_with = "year")]
+    pub month: Option<(NaiveDate, NaiveDate)>,
+
+    /// Only include this year
+    #[structopt(long)]
+    pub year: Option<i32>,
+}
+
+impl DateRangeOpts {
+    /// First and last date of the range, if bounded
+    pub fn bounds(&self) -> (Option<NaiveDate>, Option<NaiveDate>) {
+        match (self.month, self.year) {
+            (Some(range), _) => (Some(range.0), Some(range.1)),
+            (None, Some(year)) => {
+                let range = datetime::year_range(year);
+                (Some(range.0), Some(range.1))
+            }
+            (None, None) => (self.from, self.to),
+        }
+    }
+}
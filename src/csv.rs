@@ -1,26 +1,338 @@
 use chrono::NaiveDate;
-use csv::Reader;
+use csv::{Reader, StringRecord};
 use postgres::NoTls;
 use r2d2_postgres::PostgresConnectionManager;
 use regex::Regex;
 use rust_decimal::prelude::*;
+use serde::{de, Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
 use std::error::Error;
+use std::fmt;
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::BufReader;
+use std::path::Path;
 
 use crate::datetime;
 use crate::interface;
 use crate::sql;
 
+/// A csv column, given either by number (starting at 1) or by its header
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum Column {
+    Index(usize),
+    Name(String),
+}
+
+impl Column {
+    /// Position of the column in records with these headers
+    fn resolve(&self, headers: &StringRecord) -> Result<usize, String> {
+        let available = || {
+            let names: Vec<&str> = headers.iter().map(str::trim).collect();
+            names.join(", ")
+        };
+        match self {
+            Column::Index(index) if *index >= 1 && *index <= headers.len() => Ok(index - 1),
+            Column::Index(index) => Err(format!(
+                "Column {} is out of range, the file has {} columns: {}",
+                index,
+                headers.len(),
+                available()
+            )),
+            Column::Name(name) => headers
+                .iter()
+                .position(|header| header.trim().eq_ignore_ascii_case(name.trim()))
+                .ok_or_else(|| {
+                    format!(
+                        "No column named `{}`, the headers are: {}",
+                        name,
+                        available()
+                    )
+                }),
+        }
+    }
+}
+
+impl fmt::Display for Column {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Column::Index(index) => write!(f, "{}", index),
+            Column::Name(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// The `[csv]` section of the config: the default layout, plus named
+/// layouts for other brokers in `[csv.<profile>]` sections
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct CsvConfig {
+    #[serde(flatten)]
+    pub default: Profile,
+    #[serde(flatten)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+// Profile settings are never tables, so every table is a named profile and
+// the remaining keys are the default layout. serde skips
+// deny_unknown_fields on flattened structs, so this keeps typos an error.
+impl<'de> Deserialize<'de> for CsvConfig {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let table = toml::value::Table::deserialize(deserializer)?;
+        let mut default = toml::value::Table::new();
+        let mut profiles = BTreeMap::new();
+        for (key, value) in table {
+            if value.is_table() {
+                let profile = value
+                    .try_into()
+                    .map_err(|e| de::Error::custom(format!("in profile {}: {}", key, e)))?;
+                profiles.insert(key, profile);
+            } else {
+                default.insert(key, value);
+            }
+        }
+        let default = toml::Value::Table(default)
+            .try_into()
+            .map_err(de::Error::custom)?;
+        Ok(CsvConfig { default, profiles })
+    }
+}
+
+/// Layout of a portfolio csv. Columns are given by number (starting at 1)
+/// or header name.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    #[serde(default)]
+    pub currency: String,
+    #[serde(default)]
+    pub skiprows: usize,
+    #[serde(default)]
+    pub stoprows: usize,
+    #[serde(default = "default_item_column")]
+    pub item_column: Column,
+    #[serde(default = "default_value_column")]
+    pub value_column: Column,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quantity_column: Option<Column>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub price_column: Option<Column>,
+    /// Total cost basis of the holding, not per unit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost_column: Option<Column>,
+    /// Regex matched against the file name to pick this profile when none
+    /// is given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+}
+
+fn default_item_column() -> Column {
+    Column::Index(1)
+}
+
+fn default_value_column() -> Column {
+    Column::Index(2)
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Profile {
+            currency: "".to_string(),
+            skiprows: 0,
+            stoprows: 0,
+            item_column: default_item_column(),
+            value_column: default_value_column(),
+            quantity_column: None,
+            price_column: None,
+            cost_column: None,
+            filename: None,
+        }
+    }
+}
+
+impl CsvConfig {
+    /// The named profile, or else the first profile whose `filename` regex
+    /// matches the name of the file at `path`, or else the default layout
+    pub fn select(&self, name: Option<&str>, path: &str) -> Result<(String, &Profile), String> {
+        if let Some(name) = name {
+            return self
+                .profiles
+                .get(name)
+                .map(|profile| (name.to_string(), profile))
+                .ok_or_else(|| {
+                    let names: Vec<&str> = self.profiles.keys().map(|k| k.as_str()).collect();
+                    format!(
+                        "No csv profile named `{}` (available: {})",
+                        name,
+                        names.join(", ")
+                    )
+                });
+        }
+
+        let file_name = Path::new(path)
+            .file_name()
+            .map_or(path.to_string(), |f| f.to_string_lossy().to_string());
+        for (name, profile) in &self.profiles {
+            if let Some(pattern) = &profile.filename {
+                let re = Regex::new(pattern).map_err(|e| {
+                    format!("Invalid filename regex in csv profile `{}`: {}", name, e)
+                })?;
+                if re.is_match(&file_name) {
+                    return Ok((name.to_string(), profile));
+                }
+            }
+        }
+
+        Ok(("default".to_string(), &self.default))
+    }
+}
+
+/// One item of a portfolio csv
+#[derive(Debug, PartialEq)]
+pub struct Holding {
+    pub item: String,
+    pub value: Decimal,
+    pub quantity: Option<Decimal>,
+    pub price: Option<Decimal>,
+    pub cost: Option<Decimal>,
+}
+
+/// How to treat items already in the snapshot for the imported date
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImportMode {
+    /// Leave items already in the snapshot as they are
+    Skip,
+    /// Update items whose value changed
+    Overwrite,
+    /// Make the snapshot match the file, removing items not in it
+    Replace,
+}
+
+impl fmt::Display for ImportMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mode = match self {
+            ImportMode::Skip => "skip",
+            ImportMode::Overwrite => "overwrite",
+            ImportMode::Replace => "replace",
+        };
+        write!(f, "{}", mode)
+    }
+}
+
+impl FromStr for ImportMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "skip" => Ok(ImportMode::Skip),
+            "overwrite" => Ok(ImportMode::Overwrite),
+            "replace" => Ok(ImportMode::Replace),
+            _ => Err(format!("expected skip, overwrite or replace, got `{}`", s)),
+        }
+    }
+}
+
+/// What importing a holding does
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Insert,
+    /// Replaces the item in the snapshot, which had this value
+    Update(Decimal),
+    /// Removed from the snapshot because the file does not have it, or
+    /// has it at zero
+    Remove,
+    /// Already in the snapshot with the same details
+    Unchanged,
+    /// Holdings worth nothing are left out of snapshots
+    SkipZero,
+    /// Already in the snapshot with this value, which is kept
+    SkipChanged(Decimal),
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Action::Insert => write!(f, "add"),
+            Action::Update(old) => write!(f, "update (was {})", old),
+            Action::Remove => write!(f, "remove"),
+            Action::Unchanged => write!(f, "unchanged"),
+            Action::SkipZero => write!(f, "skip (zero)"),
+            Action::SkipChanged(old) => write!(f, "skip (snapshot has {})", old),
+        }
+    }
+}
+
+/// Decide what to do with each of `holdings` given the `existing` items of
+/// the snapshot. With `ImportMode::Replace`, existing items missing from
+/// `holdings` are added to the plan to be removed.
+pub fn plan_import(
+    holdings: Vec<Holding>,
+    existing: Vec<Holding>,
+    mode: ImportMode,
+) -> Vec<(Holding, Action)> {
+    let mut existing: BTreeMap<String, Holding> = existing
+        .into_iter()
+        .map(|holding| (holding.item.clone(), holding))
+        .collect();
+
+    let mut plan: Vec<(Holding, Action)> = Vec::new();
+    for holding in merge_repeated(holdings) {
+        plan.push(match existing.remove(&holding.item) {
+            None if holding.value.is_zero() => (holding, Action::SkipZero),
+            None => (holding, Action::Insert),
+            Some(old) if old == holding => (holding, Action::Unchanged),
+            Some(old) if mode == ImportMode::Skip => (holding, Action::SkipChanged(old.value)),
+            // sold since the snapshot was taken
+            Some(old) if holding.value.is_zero() => (old, Action::Remove),
+            Some(old) => (holding, Action::Update(old.value)),
+        });
+    }
+
+    if mode == ImportMode::Replace {
+        plan.extend(existing.into_values().map(|old| (old, Action::Remove)));
+    }
+
+    plan
+}
+
+/// Add together the rows of an item listed more than once, such as a fund
+/// held in two accounts with the same broker. The quantity and cost basis
+/// are only kept when every row has them.
+fn merge_repeated(holdings: Vec<Holding>) -> Vec<Holding> {
+    let mut merged: Vec<Holding> = Vec::new();
+    for holding in holdings {
+        match merged.iter_mut().find(|total| total.item == holding.item) {
+            None => merged.push(holding),
+            Some(total) => {
+                total.value += holding.value;
+                total.quantity = total.quantity.zip(holding.quantity).map(|(a, b)| a + b);
+                total.cost = total.cost.zip(holding.cost).map(|(a, b)| a + b);
+                total.price = unit_price(&total.value, &total.quantity);
+            }
+        }
+    }
+    merged
+}
+
+/// Counts of each kind of change in `plan`
+pub fn plan_summary(plan: &[(Holding, Action)]) -> String {
+    let count = |wanted: fn(&Action) -> bool| plan.iter().filter(|(_, a)| wanted(a)).count();
+    format!(
+        "{} added, {} updated, {} removed, {} unchanged, {} skipped",
+        count(|a| *a == Action::Insert),
+        count(|a| matches!(a, Action::Update(_))),
+        count(|a| *a == Action::Remove),
+        count(|a| *a == Action::Unchanged),
+        count(|a| matches!(a, Action::SkipZero | Action::SkipChanged(_))),
+    )
+}
+
 pub fn read_csv(
     pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
     path: &str,
-    currency: String,
-    skiprows: usize,
-    stoprows: usize,
-    item_col: usize,
-    value_col: usize,
+    profile: &Profile,
+    mode: ImportMode,
+    dry_run: bool,
 ) -> Result<(), Box<dyn Error>> {
     println!("{}", path);
 
@@ -37,40 +349,112 @@ pub fn read_csv(
         csv_rows.push(line.unwrap());
     }
 
-    let rows = remove_first_last_rows(csv_rows, skiprows, stoprows);
-    let data = rows.join("\n");
+    let rows = remove_first_last_rows(csv_rows, profile.skiprows, profile.stoprows);
+    let holdings = parse_holdings(&rows.join("\n"), profile)?;
+
+    // fetch the snapshot once rather than checking each holding separately
+    let existing: Vec<Holding> = sql::get_portfolio(pool.clone(), &date)?
+        .iter()
+        .map(|row| Holding {
+            item: row.get(1),
+            value: row.get(2),
+            quantity: row.get(4),
+            price: row.get(5),
+            cost: row.get(6),
+        })
+        .collect();
+    let plan = plan_import(holdings, existing, mode);
+
+    if dry_run {
+        interface::import_preview(pool, &date, &plan);
+        return Ok(());
+    }
+
+    let upserts: Vec<&Holding> = plan
+        .iter()
+        .filter(|(_, action)| matches!(action, Action::Insert | Action::Update(_)))
+        .map(|(holding, _)| holding)
+        .collect();
+    let removes: Vec<&str> = plan
+        .iter()
+        .filter(|(_, action)| *action == Action::Remove)
+        .map(|(holding, _)| holding.item.as_str())
+        .collect();
+    sql::import_portfolio(pool, &date, &upserts, &removes)?;
+
+    for (holding, action) in &plan {
+        if *action != Action::Unchanged {
+            println!("{} {}: {}", holding.item, holding.value, action);
+        }
+    }
+    println!("Snapshot on {}: {}", date, plan_summary(&plan));
+
+    Ok(())
+}
+
+/// Read the holdings out of csv `data` whose first row is the header
+fn parse_holdings(data: &str, profile: &Profile) -> Result<Vec<Holding>, Box<dyn Error>> {
     let mut rdr = Reader::from_reader(data.as_bytes());
 
+    let headers = rdr.headers()?.clone();
+    let item_col = profile.item_column.resolve(&headers)?;
+    let value_col = profile.value_column.resolve(&headers)?;
+    let resolve_optional = |col: &Option<Column>| -> Result<Option<usize>, String> {
+        col.as_ref().map(|col| col.resolve(&headers)).transpose()
+    };
+    let quantity_col = resolve_optional(&profile.quantity_column)?;
+    let price_col = resolve_optional(&profile.price_column)?;
+    let cost_col = resolve_optional(&profile.cost_column)?;
+
+    let mut holdings = Vec::new();
     for result in rdr.records() {
         let record = result?;
-        let item = &record[item_col - 1];
-        let value_input = &record[value_col - 1]
-            .replace(&currency, "")
-            .replace(",", "");
-        let value = Decimal::from_str(value_input).unwrap();
-        let exists =
-            sql::check_portfolio(pool.clone(), &date, &item, &value).expect("Problem checking");
-        if !exists && value_input != "0.00" {
-            sql::insert_portfolio(pool.clone(), &date, &item, &value).expect("Problem inserting");
-            println!("{} {} {}", date, item, value);
-            println!("Added");
-        } else if !exists && value_input == "0.00" {
-            println!("{} {} {}", date, item, value);
-            println!("Skipping 0");
-        } else {
-            println!("Already Exists");
-        }
+        let item = &record[item_col];
+        let value = parse_amount(&record[value_col], &profile.currency)
+            .ok_or_else(|| format!("Invalid value `{}` for {}", &record[value_col], item))?;
+        let optional = |col: Option<usize>| {
+            col.and_then(|col| record.get(col))
+                .and_then(|field| parse_amount(field, &profile.currency))
+        };
+        let quantity = optional(quantity_col);
+
+        holdings.push(Holding {
+            item: item.to_string(),
+            value,
+            price: optional(price_col).or_else(|| unit_price(&value, &quantity)),
+            quantity,
+            cost: optional(cost_col),
+        });
     }
 
-    Ok(())
+    Ok(holdings)
+}
+
+/// Parse an optional amount such as "$1,234.50", treating a blank field as
+/// missing
+fn parse_amount(field: &str, currency: &str) -> Option<Decimal> {
+    let field = field.replace(currency, "").replace(",", "");
+    let field = field.trim();
+    if field.is_empty() {
+        None
+    } else {
+        Decimal::from_str(field).ok()
+    }
+}
+
+/// Price per unit when the csv gives a quantity but no price
+fn unit_price(value: &Decimal, quantity: &Option<Decimal>) -> Option<Decimal> {
+    match quantity {
+        Some(quantity) if !quantity.is_zero() => Some(value / quantity),
+        _ => None,
+    }
 }
 
 fn date_from_filename(path: &str) -> NaiveDate {
     // Get date of csv
     let re = Regex::new(r"(\d{4})-(\d{2})-(\d{2})").unwrap();
     let date_string = re.captures(path).unwrap()[0].to_string();
-    let date = datetime::parse_date(&date_string).unwrap();
-    date
+    datetime::parse_date(&date_string).unwrap()
 }
 
 fn remove_first_last_rows(rows_vec: Vec<String>, skiprows: usize, stoprows: usize) -> Vec<String> {
@@ -132,4 +516,235 @@ mod tests {
         assert_eq!(d, date_from_filename(file2));
         assert_eq!(d, date_from_filename(file3));
     }
+
+    #[test]
+    fn test_parse_amount() {
+        assert_eq!(
+            Some(Decimal::new(123450, 2)),
+            parse_amount("$1,234.50", "$")
+        );
+        assert_eq!(Some(Decimal::new(12, 0)), parse_amount(" 12 ", "$"));
+        assert_eq!(None, parse_amount("", "$"));
+        assert_eq!(None, parse_amount("n/a", "$"));
+    }
+
+    #[test]
+    fn test_unit_price() {
+        let value = Decimal::new(1500, 0);
+
+        assert_eq!(
+            Some(Decimal::new(150, 0)),
+            unit_price(&value, &Some(Decimal::new(10, 0)))
+        );
+        assert_eq!(None, unit_price(&value, &Some(Decimal::zero())));
+        assert_eq!(None, unit_price(&value, &None));
+    }
+
+    #[test]
+    fn test_profile_select() {
+        let config = r#"
+            currency = "$"
+            item_column = 1
+            value_column = 2
+
+            [vanguard]
+            skiprows = 1
+            item_column = 2
+            value_column = 6
+            filename = "^vanguard_.*\\.csv$"
+
+            [fidelity]
+            currency = "USD"
+            item_column = "Symbol"
+            value_column = "Current Value"
+            filename = "(?i)fidelity"
+        "#;
+        let csv: CsvConfig = toml::from_str(config).unwrap();
+
+        assert_eq!(2, csv.profiles.len());
+
+        let (name, profile) = csv
+            .select(None, "downloads/vanguard_2021-03-14.csv")
+            .unwrap();
+        assert_eq!("vanguard", name);
+        assert_eq!(Column::Index(6), profile.value_column);
+        assert_eq!("", profile.currency);
+
+        let (name, profile) = csv.select(None, "Fidelity 2021-03-14.csv").unwrap();
+        assert_eq!("fidelity", name);
+        assert_eq!(Column::Name("Symbol".to_string()), profile.item_column);
+
+        let (name, profile) = csv.select(None, "2021-03-14.csv").unwrap();
+        assert_eq!("default", name);
+        assert_eq!("$", profile.currency);
+
+        let (name, _) = csv.select(Some("vanguard"), "2021-03-14.csv").unwrap();
+        assert_eq!("vanguard", name);
+        assert!(csv.select(Some("schwab"), "2021-03-14.csv").is_err());
+    }
+
+    #[test]
+    fn test_config_unknown_keys() {
+        let typo = toml::from_str::<CsvConfig>("value_colum = 6").unwrap_err();
+        assert!(typo.to_string().contains("unknown field `value_colum`"));
+
+        let nested = "[a.b]\nitem_column = 1";
+        assert!(toml::from_str::<CsvConfig>(nested).is_err());
+
+        let typo = toml::from_str::<CsvConfig>("[a]\nskiprow = 1").unwrap_err();
+        assert!(typo.to_string().contains("unknown field `skiprow`"));
+    }
+
+    #[test]
+    fn test_column_resolve() {
+        let headers = StringRecord::from(vec!["Account", "Symbol", " Market Value "]);
+
+        assert_eq!(Ok(0), Column::Index(1).resolve(&headers));
+        assert_eq!(
+            Ok(2),
+            Column::Name("market value".to_string()).resolve(&headers)
+        );
+        assert_eq!(
+            Err(
+                "Column 4 is out of range, the file has 3 columns: Account, Symbol, Market Value"
+                    .to_string()
+            ),
+            Column::Index(4).resolve(&headers)
+        );
+        assert!(Column::Index(0).resolve(&headers).is_err());
+        assert_eq!(
+            Err(
+                "No column named `Price`, the headers are: Account, Symbol, Market Value"
+                    .to_string()
+            ),
+            Column::Name("Price".to_string()).resolve(&headers)
+        );
+    }
+
+    #[test]
+    fn test_parse_holdings() {
+        let profile = Profile {
+            currency: "$".to_string(),
+            item_column: Column::Name("Symbol".to_string()),
+            value_column: Column::Index(3),
+            quantity_column: Some(Column::Name("Shares".to_string())),
+            ..Profile::default()
+        };
+        let data = "Account,Symbol,Value,Shares\nX,VTI,\"$1,200.00\",6\nX,CASH,$0.00,";
+
+        assert_eq!(
+            vec![
+                Holding {
+                    item: "VTI".to_string(),
+                    value: Decimal::new(120000, 2),
+                    quantity: Some(Decimal::new(6, 0)),
+                    price: Some(Decimal::new(200, 0)),
+                    cost: None,
+                },
+                Holding {
+                    item: "CASH".to_string(),
+                    value: Decimal::new(0, 2),
+                    quantity: None,
+                    price: None,
+                    cost: None,
+                },
+            ],
+            parse_holdings(data, &profile).unwrap()
+        );
+        assert!(parse_holdings("Symbol,Value\nVTI,n/a", &Profile::default()).is_err());
+    }
+
+    #[test]
+    fn test_plan_import() {
+        let holding = |item: &str, value: i64| Holding {
+            item: item.to_string(),
+            value: Decimal::new(value, 0),
+            quantity: None,
+            price: None,
+            cost: None,
+        };
+        let file = || {
+            vec![
+                holding("VTI", 120),
+                holding("BND", 50),
+                holding("CASH", 0),
+                holding("VXUS", 30),
+                holding("MMF", 0),
+            ]
+        };
+        let existing = || {
+            vec![
+                holding("BND", 50),
+                holding("VTI", 100),
+                holding("GLD", 10),
+                holding("CASH", 20),
+            ]
+        };
+        let actions = |mode: ImportMode| -> Vec<(String, Action)> {
+            plan_import(file(), existing(), mode)
+                .into_iter()
+                .map(|(holding, action)| (holding.item, action))
+                .collect()
+        };
+
+        let mut expected = vec![
+            ("VTI".to_string(), Action::SkipChanged(Decimal::new(100, 0))),
+            ("BND".to_string(), Action::Unchanged),
+            ("CASH".to_string(), Action::SkipChanged(Decimal::new(20, 0))),
+            ("VXUS".to_string(), Action::Insert),
+            ("MMF".to_string(), Action::SkipZero),
+        ];
+        assert_eq!(expected, actions(ImportMode::Skip));
+
+        expected[0].1 = Action::Update(Decimal::new(100, 0));
+        expected[2].1 = Action::Remove;
+        assert_eq!(expected, actions(ImportMode::Overwrite));
+        // a removal carries the value being taken out of the snapshot
+        let removed = &plan_import(file(), existing(), ImportMode::Overwrite)[2].0;
+        assert_eq!(Decimal::new(20, 0), removed.value);
+
+        expected.push(("GLD".to_string(), Action::Remove));
+        assert_eq!(expected, actions(ImportMode::Replace));
+        assert_eq!(
+            "1 added, 1 updated, 2 removed, 1 unchanged, 1 skipped",
+            plan_summary(&plan_import(file(), existing(), ImportMode::Replace))
+        );
+    }
+
+    #[test]
+    fn test_merge_repeated() {
+        let holding = |item: &str, value: i64, quantity: Option<i64>, cost: i64| Holding {
+            item: item.to_string(),
+            value: Decimal::new(value, 0),
+            quantity: quantity.map(|q| Decimal::new(q, 0)),
+            price: quantity.map(|q| Decimal::new(value / q, 0)),
+            cost: Some(Decimal::new(cost, 0)),
+        };
+        let file = vec![
+            holding("VTI", 100, Some(1), 80),
+            holding("BND", 50, None, 50),
+            holding("VTI", 300, Some(2), 250),
+            holding("BND", 20, Some(1), 25),
+        ];
+
+        assert_eq!(
+            vec![
+                Holding {
+                    item: "VTI".to_string(),
+                    value: Decimal::new(400, 0),
+                    quantity: Some(Decimal::new(3, 0)),
+                    price: Some(Decimal::new(400, 0) / Decimal::new(3, 0)),
+                    cost: Some(Decimal::new(330, 0)),
+                },
+                Holding {
+                    item: "BND".to_string(),
+                    value: Decimal::new(70, 0),
+                    quantity: None,
+                    price: None,
+                    cost: Some(Decimal::new(75, 0)),
+                },
+            ],
+            merge_repeated(file)
+        );
+    }
 }
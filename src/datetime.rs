@@ -1,8 +1,330 @@
 use chrono::format::ParseError;
-use chrono::NaiveDate;
+use chrono::{Datelike, Duration, NaiveDate};
+use std::fmt;
+use std::str::FromStr;
 
 pub fn parse_date(date_str: &str) -> Result<NaiveDate, ParseError> {
     let date = NaiveDate::parse_from_str(date_str, "%Y-%m-%d")?;
 
     Ok(date)
 }
+
+/// First and last day of a month given as YYYY-MM
+pub fn month_range(month_str: &str) -> Result<(NaiveDate, NaiveDate), ParseError> {
+    let first = NaiveDate::parse_from_str(&format!("{}-01", month_str), "%Y-%m-%d")?;
+    let next = if first.month() == 12 {
+        NaiveDate::from_ymd(first.year() + 1, 1, 1)
+    } else {
+        NaiveDate::from_ymd(first.year(), first.month() + 1, 1)
+    };
+
+    Ok((first, next.pred()))
+}
+
+/// First and last day of a year
+pub fn year_range(year: i32) -> (NaiveDate, NaiveDate) {
+    (
+        NaiveDate::from_ymd(year, 1, 1),
+        NaiveDate::from_ymd(year, 12, 31),
+    )
+}
+
+/// Length of the buckets expenses are grouped into in reports
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Period {
+    Week,
+    Month,
+    Year,
+}
+
+impl Period {
+    /// Field name understood by PostgreSQL's date_trunc
+    pub fn trunc_field(&self) -> &'static str {
+        match self {
+            Period::Week => "week",
+            Period::Month => "month",
+            Period::Year => "year",
+        }
+    }
+
+    /// Label for the period starting on `start`
+    pub fn label(&self, start: &NaiveDate) -> String {
+        match self {
+            Period::Week => start.format("%G-W%V").to_string(),
+            Period::Month => start.format("%Y-%m").to_string(),
+            Period::Year => start.format("%Y").to_string(),
+        }
+    }
+
+    /// Start of the period containing `date`
+    pub fn start_of(&self, date: &NaiveDate) -> NaiveDate {
+        match self {
+            Period::Week => *date - Duration::days(date.weekday().num_days_from_monday() as i64),
+            Period::Month => NaiveDate::from_ymd(date.year(), date.month(), 1),
+            Period::Year => NaiveDate::from_ymd(date.year(), 1, 1),
+        }
+    }
+
+    /// Start of the period before the one starting on `start`
+    pub fn previous(&self, start: &NaiveDate) -> NaiveDate {
+        self.start_of(&start.pred())
+    }
+
+    /// Start of the period following the one starting on `start`
+    pub fn next(&self, start: &NaiveDate) -> NaiveDate {
+        match self {
+            Period::Week => *start + Duration::weeks(1),
+            Period::Month if start.month() == 12 => NaiveDate::from_ymd(start.year() + 1, 1, 1),
+            Period::Month => NaiveDate::from_ymd(start.year(), start.month() + 1, 1),
+            Period::Year => NaiveDate::from_ymd(start.year() + 1, 1, 1),
+        }
+    }
+
+    /// Starts of every period from `first` up to and including `last`
+    pub fn range(&self, first: &NaiveDate, last: &NaiveDate) -> Vec<NaiveDate> {
+        let mut starts = Vec::new();
+        let mut start = *first;
+        while start <= *last {
+            starts.push(start);
+            start = self.next(&start);
+        }
+        starts
+    }
+}
+
+impl fmt::Display for Period {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.trunc_field())
+    }
+}
+
+impl FromStr for Period {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "week" => Ok(Period::Week),
+            "month" => Ok(Period::Month),
+            "year" => Ok(Period::Year),
+            _ => Err(format!("expected week, month or year, got `{}`", s)),
+        }
+    }
+}
+
+/// The same day `months` months after `date`, or the last day of that
+/// month if it is shorter
+pub fn add_months(date: &NaiveDate, months: u32) -> NaiveDate {
+    let index = date.year() * 12 + date.month0() as i32 + months as i32;
+    let (year, month) = (index / 12, index as u32 % 12 + 1);
+    let last_day = Period::Month
+        .next(&NaiveDate::from_ymd(year, month, 1))
+        .pred()
+        .day();
+
+    NaiveDate::from_ymd(year, month, date.day().min(last_day))
+}
+
+/// How often a subscription is charged
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BillingInterval {
+    Week,
+    Month,
+    Quarter,
+    Year,
+}
+
+impl BillingInterval {
+    /// Number of charges in a year
+    pub fn per_year(&self) -> u32 {
+        match self {
+            BillingInterval::Week => 52,
+            BillingInterval::Month => 12,
+            BillingInterval::Quarter => 4,
+            BillingInterval::Year => 1,
+        }
+    }
+
+    /// Date of the `n`th renewal after `start`. Counting from the start date
+    /// keeps a subscription started on the 31st on the last day of shorter
+    /// months without drifting.
+    pub fn renewal(&self, start: &NaiveDate, n: u32) -> NaiveDate {
+        match self {
+            BillingInterval::Week => *start + Duration::weeks(n as i64),
+            BillingInterval::Month => add_months(start, n),
+            BillingInterval::Quarter => add_months(start, 3 * n),
+            BillingInterval::Year => add_months(start, 12 * n),
+        }
+    }
+
+    /// Renewals of a subscription started on `start` falling between `from`
+    /// and `to` inclusive. The start date itself counts as the first charge.
+    pub fn renewals_between(
+        &self,
+        start: &NaiveDate,
+        from: &NaiveDate,
+        to: &NaiveDate,
+    ) -> Vec<NaiveDate> {
+        let mut renewals = Vec::new();
+        let mut n = 0;
+        loop {
+            let renewal = self.renewal(start, n);
+            if renewal > *to {
+                break;
+            }
+            if renewal >= *from {
+                renewals.push(renewal);
+            }
+            n += 1;
+        }
+        renewals
+    }
+
+    /// First renewal on or after `date`
+    pub fn next_renewal(&self, start: &NaiveDate, date: &NaiveDate) -> NaiveDate {
+        let mut n = 0;
+        while self.renewal(start, n) < *date {
+            n += 1;
+        }
+        self.renewal(start, n)
+    }
+}
+
+impl fmt::Display for BillingInterval {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            BillingInterval::Week => "week",
+            BillingInterval::Month => "month",
+            BillingInterval::Quarter => "quarter",
+            BillingInterval::Year => "year",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for BillingInterval {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "week" => Ok(BillingInterval::Week),
+            "month" => Ok(BillingInterval::Month),
+            "quarter" => Ok(BillingInterval::Quarter),
+            "year" => Ok(BillingInterval::Year),
+            _ => Err(format!(
+                "expected week, month, quarter or year, got `{}`",
+                s
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_month_range() {
+        assert_eq!(
+            (
+                NaiveDate::from_ymd(2021, 2, 1),
+                NaiveDate::from_ymd(2021, 2, 28)
+            ),
+            month_range("2021-02").unwrap()
+        );
+        assert_eq!(
+            (
+                NaiveDate::from_ymd(2020, 12, 1),
+                NaiveDate::from_ymd(2020, 12, 31)
+            ),
+            month_range("2020-12").unwrap()
+        );
+        assert!(month_range("2020-13").is_err());
+    }
+
+    #[test]
+    fn test_period_range() {
+        let first = NaiveDate::from_ymd(2020, 11, 1);
+        let last = NaiveDate::from_ymd(2021, 2, 1);
+        let months: Vec<String> = Period::Month
+            .range(&first, &last)
+            .iter()
+            .map(|d| Period::Month.label(d))
+            .collect();
+
+        assert_eq!(vec!["2020-11", "2020-12", "2021-01", "2021-02"], months);
+        assert_eq!(
+            "2021-W01",
+            Period::Week.label(&NaiveDate::from_ymd(2021, 1, 4))
+        );
+        assert_eq!(
+            NaiveDate::from_ymd(2021, 1, 11),
+            Period::Week.next(&NaiveDate::from_ymd(2021, 1, 4))
+        );
+    }
+
+    #[test]
+    fn test_period_start_of() {
+        let date = NaiveDate::from_ymd(2021, 3, 14);
+
+        assert_eq!(
+            NaiveDate::from_ymd(2021, 3, 8),
+            Period::Week.start_of(&date)
+        );
+        assert_eq!(
+            NaiveDate::from_ymd(2021, 3, 1),
+            Period::Month.start_of(&date)
+        );
+        assert_eq!(
+            NaiveDate::from_ymd(2021, 2, 1),
+            Period::Month.previous(&NaiveDate::from_ymd(2021, 3, 1))
+        );
+        assert_eq!(
+            NaiveDate::from_ymd(2021, 1, 1),
+            Period::Year.start_of(&date)
+        );
+    }
+
+    #[test]
+    fn test_add_months() {
+        assert_eq!(
+            NaiveDate::from_ymd(2021, 2, 28),
+            add_months(&NaiveDate::from_ymd(2021, 1, 31), 1)
+        );
+        assert_eq!(
+            NaiveDate::from_ymd(2022, 1, 15),
+            add_months(&NaiveDate::from_ymd(2021, 11, 15), 2)
+        );
+    }
+
+    #[test]
+    fn test_billing_interval_renewals() {
+        let start = NaiveDate::from_ymd(2021, 1, 31);
+
+        assert_eq!(
+            NaiveDate::from_ymd(2021, 3, 31),
+            BillingInterval::Month.next_renewal(&start, &NaiveDate::from_ymd(2021, 3, 1))
+        );
+        assert_eq!(
+            NaiveDate::from_ymd(2021, 4, 30),
+            BillingInterval::Quarter.renewal(&start, 1)
+        );
+        assert_eq!(
+            vec![
+                NaiveDate::from_ymd(2021, 2, 7),
+                NaiveDate::from_ymd(2021, 2, 14)
+            ],
+            BillingInterval::Week.renewals_between(
+                &start,
+                &NaiveDate::from_ymd(2021, 2, 1),
+                &NaiveDate::from_ymd(2021, 2, 14)
+            )
+        );
+        assert!(BillingInterval::Year
+            .renewals_between(
+                &start,
+                &NaiveDate::from_ymd(2021, 2, 1),
+                &NaiveDate::from_ymd(2021, 12, 31)
+            )
+            .is_empty());
+    }
+}
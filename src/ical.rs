@@ -0,0 +1,138 @@
+use chrono::{Datelike, NaiveDate, NaiveDateTime};
+
+use crate::datetime::BillingInterval;
+
+/// A recurring all-day event
+pub struct Event {
+    pub uid: String,
+    pub summary: String,
+    pub description: String,
+    pub start: NaiveDate,
+    pub rrule: String,
+}
+
+/// RRULE repeating every `interval` on the day of month of `anchor`.
+/// Days after the 28th fall back to the last day of shorter months, the
+/// same way renewals are counted in `BillingInterval::renewal`.
+pub fn rrule(interval: &BillingInterval, anchor: &NaiveDate) -> String {
+    let mut rule = match interval {
+        BillingInterval::Week => "FREQ=WEEKLY".to_string(),
+        BillingInterval::Month => "FREQ=MONTHLY".to_string(),
+        BillingInterval::Quarter => "FREQ=MONTHLY;INTERVAL=3".to_string(),
+        BillingInterval::Year => "FREQ=YEARLY".to_string(),
+    };
+
+    if *interval != BillingInterval::Week && anchor.day() > 28 {
+        if *interval == BillingInterval::Year {
+            rule.push_str(&format!(";BYMONTH={}", anchor.month()));
+        }
+        let days: Vec<String> = (28..=anchor.day()).map(|day| day.to_string()).collect();
+        rule.push_str(&format!(";BYMONTHDAY={};BYSETPOS=-1", days.join(",")));
+    }
+
+    rule
+}
+
+/// Escape a TEXT value (RFC 5545 section 3.3.11)
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+/// Fold a content line to at most 75 octets per line, without splitting
+/// a UTF-8 character
+fn fold(line: &str) -> String {
+    let mut folded = String::new();
+    let mut length = 0;
+    for c in line.chars() {
+        let width = c.len_utf8();
+        // continuation lines start with a space, which counts towards the limit
+        if length + width > 75 {
+            folded.push_str("\r\n ");
+            length = 1;
+        }
+        folded.push(c);
+        length += width;
+    }
+    folded.push_str("\r\n");
+    folded
+}
+
+/// An iCalendar file holding `events`, stamped with the UTC time `stamp`
+pub fn calendar(events: &[Event], stamp: &NaiveDateTime) -> String {
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//haibun//haibun-cli//EN".to_string(),
+        "CALSCALE:GREGORIAN".to_string(),
+    ];
+
+    for event in events {
+        lines.push("BEGIN:VEVENT".to_string());
+        lines.push(format!("UID:{}", event.uid));
+        lines.push(format!("DTSTAMP:{}", stamp.format("%Y%m%dT%H%M%SZ")));
+        lines.push(format!(
+            "DTSTART;VALUE=DATE:{}",
+            event.start.format("%Y%m%d")
+        ));
+        lines.push(format!("RRULE:{}", event.rrule));
+        lines.push(format!("SUMMARY:{}", escape(&event.summary)));
+        lines.push(format!("DESCRIPTION:{}", escape(&event.description)));
+        lines.push("TRANSP:TRANSPARENT".to_string());
+        lines.push("END:VEVENT".to_string());
+    }
+    lines.push("END:VCALENDAR".to_string());
+
+    lines.iter().map(|line| fold(line)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rrule() {
+        assert_eq!(
+            "FREQ=MONTHLY",
+            rrule(&BillingInterval::Month, &NaiveDate::from_ymd(2021, 1, 15))
+        );
+        assert_eq!(
+            "FREQ=MONTHLY;INTERVAL=3;BYMONTHDAY=28,29,30;BYSETPOS=-1",
+            rrule(&BillingInterval::Quarter, &NaiveDate::from_ymd(2021, 4, 30))
+        );
+        assert_eq!(
+            "FREQ=YEARLY;BYMONTH=2;BYMONTHDAY=28,29;BYSETPOS=-1",
+            rrule(&BillingInterval::Year, &NaiveDate::from_ymd(2020, 2, 29))
+        );
+        assert_eq!(
+            "FREQ=WEEKLY",
+            rrule(&BillingInterval::Week, &NaiveDate::from_ymd(2021, 1, 31))
+        );
+    }
+
+    #[test]
+    fn test_calendar() {
+        let events = vec![Event {
+            uid: "subscription-1@haibun".to_string(),
+            summary: "Netflix renews".to_string(),
+            description:
+                "Category: Entertainment\nPrice: 15.49, every month; paid from the Checking account"
+                    .to_string(),
+            start: NaiveDate::from_ymd(2021, 1, 31),
+            rrule: rrule(&BillingInterval::Month, &NaiveDate::from_ymd(2021, 1, 31)),
+        }];
+        let stamp = NaiveDate::from_ymd(2021, 3, 14).and_hms(9, 26, 53);
+        let ics = calendar(&events, &stamp);
+
+        assert!(ics.starts_with("BEGIN:VCALENDAR\r\nVERSION:2.0\r\n"));
+        assert!(ics.contains("DTSTAMP:20210314T092653Z\r\n"));
+        assert!(ics.contains("DTSTART;VALUE=DATE:20210131\r\n"));
+        assert!(ics.contains(
+            "DESCRIPTION:Category: Entertainment\\nPrice: 15.49\\, every month\\; paid from\r\n  the Checking account\r\n"
+        ));
+        assert!(ics.ends_with("END:VEVENT\r\nEND:VCALENDAR\r\n"));
+        assert!(ics.lines().all(|line| line.len() <= 76));
+    }
+}
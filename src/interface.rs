@@ -1,12 +1,16 @@
-use chrono::NaiveDate;
+use chrono::{Local, NaiveDate, NaiveDateTime};
 use comfy_table::presets::ASCII_MARKDOWN;
 use comfy_table::*;
 use dialoguer::{Confirm, Input};
 use postgres::{NoTls, Row};
 use r2d2_postgres::PostgresConnectionManager;
 use rust_decimal::prelude::*;
+use std::fmt;
+use std::io::IsTerminal;
 
-use crate::datetime;
+use crate::csv::{self, Action, Holding};
+use crate::datetime::{self, BillingInterval, Period};
+use crate::ical;
 use crate::sql;
 
 /// Ask user for input and return entered integer
@@ -27,8 +31,51 @@ pub fn user_input_text(displayed_text: &str) -> String {
 
 pub fn user_input_date(displayed_text: &str) -> NaiveDate {
     let value: String = Input::new().with_prompt(displayed_text).interact().unwrap();
-    let date = datetime::parse_date(&value).unwrap();
-    date
+    datetime::parse_date(&value).unwrap()
+}
+
+/// Ask user for input that may be left blank
+pub fn user_input_optional(displayed_text: &str) -> Option<String> {
+    let value: String = Input::new()
+        .with_prompt(displayed_text)
+        .allow_empty(true)
+        .interact()
+        .unwrap();
+    if value.trim().is_empty() {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+/// Ask user for input, pre-filled with the current value
+pub fn user_input_default<T>(displayed_text: &str, default: T) -> T
+where
+    T: Clone + std::fmt::Display + FromStr,
+    T::Err: std::fmt::Display + std::fmt::Debug,
+{
+    Input::new()
+        .with_prompt(displayed_text)
+        .default(default)
+        .interact()
+        .unwrap()
+}
+
+/// Ask user for input, pre-filled with the current value when there is one
+pub fn user_input_current<T>(displayed_text: &str, current: Option<T>) -> T
+where
+    T: Clone + std::fmt::Display + FromStr,
+    T::Err: std::fmt::Display + std::fmt::Debug,
+{
+    match current {
+        Some(current) => user_input_default(displayed_text, current),
+        None => Input::new().with_prompt(displayed_text).interact().unwrap(),
+    }
+}
+
+/// Format an amount rounded to two decimal places
+fn money(amount: &Decimal) -> String {
+    format!("{:.2}", amount.round_dp(2))
 }
 
 pub fn user_input_confirm(displayed_text: &str) -> bool {
@@ -52,25 +99,97 @@ pub fn expense_rows_to_table(rows: Vec<Row>) -> String {
 
     for row in rows {
         let id: i32 = row.get(0);
-        let date: NaiveDate = row.get(1);
-        let account: &str = row.get(2);
-        let amount: &str = row.get(3);
-        let category: &str = row.get(4);
-        let notes: &str = row.get(5);
+        let date: Option<NaiveDate> = row.get(1);
+        let account: Option<&str> = row.get(2);
+        let amount: Option<&str> = row.get(3);
+        let category: Option<&str> = row.get(4);
+        let notes: Option<&str> = row.get(5);
 
         table.add_row(vec![
             Cell::new(id),
-            Cell::new(date),
-            Cell::new(account),
-            Cell::new(amount),
-            Cell::new(category),
-            Cell::new(notes),
+            Cell::new(date.map_or(String::new(), |date| date.to_string())),
+            Cell::new(account.unwrap_or("")),
+            Cell::new(amount.unwrap_or("")),
+            Cell::new(category.unwrap_or("")),
+            Cell::new(notes.unwrap_or("")),
         ]);
     }
 
     table.to_string()
 }
 
+/// A category at its position in the category tree
+struct CategoryNode {
+    id: i32,
+    name: String,
+    parent: Option<i32>,
+    depth: usize,
+}
+
+impl CategoryNode {
+    /// Name indented by depth
+    fn indented_name(&self) -> String {
+        format!("{}{}", "  ".repeat(self.depth), self.name)
+    }
+}
+
+fn add_subtree(
+    category: &(i32, String, Option<i32>),
+    depth: usize,
+    categories: &[(i32, String, Option<i32>)],
+    tree: &mut Vec<CategoryNode>,
+) {
+    tree.push(CategoryNode {
+        id: category.0,
+        name: category.1.to_string(),
+        parent: category.2,
+        depth,
+    });
+    for child in categories.iter().filter(|c| c.2 == Some(category.0)) {
+        add_subtree(child, depth + 1, categories, tree);
+    }
+}
+
+/// Order (id, name, parent id) categories depth-first, each followed by its
+/// children, with siblings sorted by name
+fn category_tree(mut categories: Vec<(i32, String, Option<i32>)>) -> Vec<CategoryNode> {
+    categories.sort_by(|a, b| a.1.cmp(&b.1));
+    let ids: Vec<i32> = categories.iter().map(|c| c.0).collect();
+
+    let mut tree: Vec<CategoryNode> = Vec::new();
+    for category in &categories {
+        let is_root = match category.2 {
+            Some(parent) => !ids.contains(&parent),
+            None => true,
+        };
+        if is_root {
+            add_subtree(category, 0, &categories, &mut tree);
+        }
+    }
+
+    // Categories caught in a parent cycle are never reached from a root
+    for category in &categories {
+        if !tree.iter().any(|node| node.id == category.0) {
+            tree.push(CategoryNode {
+                id: category.0,
+                name: category.1.to_string(),
+                parent: category.2,
+                depth: 0,
+            });
+        }
+    }
+
+    tree
+}
+
+fn category_rows_to_tree(rows: Vec<Row>) -> Vec<CategoryNode> {
+    category_tree(
+        rows.iter()
+            .map(|row| (row.get(0), row.get(1), row.get(2)))
+            .collect(),
+    )
+}
+
 pub fn expense_category_rows_to_table(rows: Vec<Row>) -> String {
     let mut table = comfy_table::Table::new();
     table
@@ -78,11 +197,114 @@ pub fn expense_category_rows_to_table(rows: Vec<Row>) -> String {
         .set_content_arrangement(ContentArrangement::Dynamic)
         .set_header(vec!["id", "Category"]);
 
-    for row in rows {
-        let id: i32 = row.get(0);
-        let category: String = row.get(1);
+    for node in category_rows_to_tree(rows) {
+        table.add_row(vec![Cell::new(node.id), Cell::new(node.indented_name())]);
+    }
+
+    table.to_string()
+}
+
+/// Totals per category (rows) per period (columns)
+struct Pivot {
+    periods: Vec<NaiveDate>,
+    rows: Vec<(String, Vec<Decimal>)>,
+    totals: Vec<Decimal>,
+}
+
+/// Arrange (category id, period start, total) entries into a pivot with a
+/// column for every period between the first and last one seen. Each
+/// category's row includes the totals of its descendants, and categories
+/// with nothing spent are left out.
+fn pivot(
+    entries: &[(Option<i32>, NaiveDate, Decimal)],
+    tree: &[CategoryNode],
+    period: &Period,
+) -> Pivot {
+    let periods = match (
+        entries.iter().map(|e| e.1).min(),
+        entries.iter().map(|e| e.1).max(),
+    ) {
+        (Some(first), Some(last)) => period.range(&first, &last),
+        _ => Vec::new(),
+    };
+
+    let mut values = vec![vec![Decimal::zero(); periods.len()]; tree.len()];
+    let mut uncategorized = vec![Decimal::zero(); periods.len()];
+    let mut totals = vec![Decimal::zero(); periods.len()];
+    let mut used = vec![false; tree.len()];
+    let mut has_uncategorized = false;
+
+    for (category_id, start, total) in entries {
+        let column = periods.iter().position(|p| p == start).unwrap();
+        totals[column] += *total;
+
+        let mut current = *category_id;
+        let mut steps = 0;
+        if current.is_none() {
+            uncategorized[column] += *total;
+            has_uncategorized = true;
+        }
+        // Add to the category and each of its ancestors
+        while let Some(index) = current.and_then(|id| tree.iter().position(|n| n.id == id)) {
+            values[index][column] += *total;
+            used[index] = true;
+            current = tree[index].parent;
+            steps += 1;
+            if steps > tree.len() {
+                break;
+            }
+        }
+    }
 
-        table.add_row(vec![Cell::new(id), Cell::new(category)]);
+    let mut rows: Vec<(String, Vec<Decimal>)> = tree
+        .iter()
+        .zip(values)
+        .zip(used)
+        .filter(|(_, used)| *used)
+        .map(|((node, values), _)| (node.indented_name(), values))
+        .collect();
+    if has_uncategorized {
+        rows.push(("Uncategorized".to_string(), uncategorized));
+    }
+
+    Pivot {
+        periods,
+        rows,
+        totals,
+    }
+}
+
+pub fn expense_report_to_table(rows: Vec<Row>, categories: Vec<Row>, period: &Period) -> String {
+    let entries: Vec<(Option<i32>, NaiveDate, Decimal)> = rows
+        .iter()
+        .map(|row| (row.get(0), row.get(1), row.get(2)))
+        .collect();
+    let tree = category_rows_to_tree(categories);
+    let pivot = pivot(&entries, &tree, period);
+
+    let mut header = vec!["Category".to_string()];
+    header.extend(pivot.periods.iter().map(|p| period.label(p)));
+    header.push("Total".to_string());
+    header.push("Average".to_string());
+
+    let mut table = comfy_table::Table::new();
+    table
+        .load_preset(ASCII_MARKDOWN)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(header);
+
+    let count = Decimal::from(pivot.periods.len().max(1));
+
+    for (category, values) in pivot.rows.iter().chain(std::iter::once(&(
+        "Total".to_string(),
+        pivot.totals.clone(),
+    ))) {
+        let total: Decimal = values.iter().sum();
+        let mut cells = vec![Cell::new(category)];
+        cells.extend(values.iter().map(|v| Cell::new(money(v))));
+        cells.push(Cell::new(money(&total)));
+        cells.push(Cell::new(money(&(total / count))));
+        table.add_row(cells);
     }
 
     table.to_string()
@@ -93,194 +315,2596 @@ pub fn portfolio_rows_to_table(rows: Vec<Row>) -> String {
     table
         .load_preset(ASCII_MARKDOWN)
         .set_content_arrangement(ContentArrangement::Dynamic)
-        .set_header(vec!["ID", "Item", "Value", "Proportion"]);
+        .set_header(vec![
+            "ID",
+            "Item",
+            "Quantity",
+            "Price",
+            "Value",
+            "Proportion",
+            "Cost Basis",
+            "Gain/Loss",
+            "Return",
+        ]);
+
+    let optional = |amount: Option<Decimal>| amount.map_or(String::new(), |a| money(&a));
 
     for row in rows {
         let id: i32 = row.get(0);
         let item: &str = row.get(1);
         let value: Decimal = row.get(2);
         let proportion: &str = row.get(3);
+        let quantity: Option<Decimal> = row.get(4);
+        let price: Option<Decimal> = row.get(5);
+        let cost_basis: Option<Decimal> = row.get(6);
+        let gain = cost_basis.map(|cost| value - cost);
 
         table.add_row(vec![
             Cell::new(id),
             Cell::new(item),
-            Cell::new(value),
+            Cell::new(quantity.map_or(String::new(), |q| q.normalize().to_string())),
+            Cell::new(optional(price)),
+            Cell::new(money(&value)),
             Cell::new(proportion),
+            Cell::new(optional(cost_basis)),
+            Cell::new(gain.map_or(String::new(), |g| format!("{:+.2}", g.round_dp(2)))),
+            Cell::new(
+                cost_basis
+                    .and_then(|cost| percent_change(&cost, &value))
+                    .map_or(String::new(), |p| format!("{:+.2}%", p.round_dp(2))),
+            ),
         ]);
     }
 
     table.to_string()
 }
 
-pub fn subscription_rows_to_table(rows: Vec<Row>) -> String {
+/// Show the latest portfolio snapshot taken on or before `date`
+pub fn portfolio_view(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    date: Option<NaiveDate>,
+) -> Result<(), String> {
+    let snapshot = portfolio_snapshot(pool.clone(), &date)?;
+
+    let mut table_vec: Vec<Row> = sql::get_portfolio(pool.clone(), &snapshot).unwrap();
+    let table_vec_sum: Row = sql::get_portfolio_sum(pool.clone(), &snapshot).unwrap();
+    table_vec.push(table_vec_sum);
+    println!("Snapshot of {}", snapshot);
+    println!("{}", portfolio_rows_to_table(table_vec));
+    println!("{}", sql::portfolio_count(pool.clone(), &snapshot).unwrap());
+
+    Ok(())
+}
+
+/// Correct the value of an item in a snapshot, or remove it with a value of
+/// zero, prompting for whatever is not given
+pub fn manage_portfolio(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    date: Option<NaiveDate>,
+    id: Option<i32>,
+    value: Option<Decimal>,
+) -> Result<(), String> {
+    let snapshot = portfolio_snapshot(pool.clone(), &date)?;
+
+    let id = match id {
+        Some(id) => id,
+        None => {
+            require_prompt("id")?;
+            portfolio_view(pool.clone(), Some(snapshot))?;
+            user_input_int("ID of item to update")
+        }
+    };
+    let current = sql::get_portfolio_item(pool.clone(), &id, &snapshot)
+        .map_err(|e| format!("Could not read portfolio: {}", e))?
+        .ok_or(format!("No item {} in the snapshot of {}", id, snapshot))?;
+    let item: String = current.get(0);
+
+    let value = match value {
+        Some(value) => value,
+        None => {
+            require_prompt("value")?;
+            user_input_default("New value (0 to remove)", current.get::<_, Decimal>(1))
+        }
+    };
+    if value < Decimal::zero() {
+        return Err(format!("Value must not be negative, got {}", value));
+    }
+
+    if value.is_zero() {
+        sql::delete_portfolio_item(pool.clone(), &id)
+            .map_err(|e| format!("Could not remove item: {}", e))?;
+        println!("Removed {} from the snapshot of {}", item, snapshot);
+    } else {
+        sql::update_portfolio_value(pool.clone(), &id, &value)
+            .map_err(|e| format!("Could not update item: {}", e))?;
+        println!(
+            "Set {} to {} in the snapshot of {}",
+            item,
+            money(&value),
+            snapshot
+        );
+    }
+
+    Ok(())
+}
+
+/// Date of the latest snapshot on or before `date`
+fn portfolio_snapshot(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    date: &Option<NaiveDate>,
+) -> Result<NaiveDate, String> {
+    sql::get_portfolio_date(pool, date)
+        .unwrap()
+        .ok_or_else(|| match date {
+            Some(date) => format!("No portfolio snapshot on or before {}", date),
+            None => "No portfolio snapshots yet".to_string(),
+        })
+}
+
+pub fn portfolio_dates_to_table(rows: Vec<Row>) -> String {
     let mut table = comfy_table::Table::new();
     table
         .load_preset(ASCII_MARKDOWN)
         .set_content_arrangement(ContentArrangement::Dynamic)
-        .set_header(vec!["Name", "Category", "Yearly", "Monthly"]);
+        .set_header(vec!["Date", "Items", "Total"]);
 
     for row in rows {
-        let name: &str = row.get(0);
-        let category: &str = row.get(1);
-        let yearly: Decimal = row.get(2);
-        let monthly: &str = row.get(3);
+        let date: NaiveDate = row.get(0);
+        let count: i64 = row.get(1);
+        let total: Decimal = row.get(2);
 
         table.add_row(vec![
-            Cell::new(name),
-            Cell::new(category),
-            Cell::new(yearly),
-            Cell::new(monthly),
+            Cell::new(date),
+            Cell::new(count),
+            Cell::new(money(&total)),
         ]);
     }
 
     table.to_string()
 }
 
-pub fn account_rows_to_table(rows: Vec<Row>) -> String {
+/// Change from `old` to `new` as a percentage of `old`
+fn percent_change(old: &Decimal, new: &Decimal) -> Option<Decimal> {
+    if old.is_zero() {
+        None
+    } else {
+        Some((new - old) / old * Decimal::from(100))
+    }
+}
+
+/// Compare the snapshots taken on or before `from` and `to` item by item
+pub fn portfolio_diff(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<(), String> {
+    let from = portfolio_snapshot(pool.clone(), &Some(from))?;
+    let to = portfolio_snapshot(pool.clone(), &Some(to))?;
+    let rows: Vec<Row> = sql::get_portfolio_diff(pool, &from, &to).unwrap();
+
     let mut table = comfy_table::Table::new();
     table
         .load_preset(ASCII_MARKDOWN)
         .set_content_arrangement(ContentArrangement::Dynamic)
-        .set_header(vec!["id", "Account"]);
+        .set_header(vec![
+            "Item".to_string(),
+            from.to_string(),
+            to.to_string(),
+            "Change".to_string(),
+            "Change %".to_string(),
+            "".to_string(),
+        ]);
 
+    let mut old_total = Decimal::zero();
+    let mut new_total = Decimal::zero();
     for row in rows {
-        let id: i32 = row.get(0);
-        let account: &str = row.get(1);
+        let item: &str = row.get(0);
+        let old: Option<Decimal> = row.get(1);
+        let new: Option<Decimal> = row.get(2);
+        let status = match (old, new) {
+            (None, Some(_)) => "new",
+            (Some(_), None) => "gone",
+            _ => "",
+        };
+        let old = old.unwrap_or_else(Decimal::zero);
+        let new = new.unwrap_or_else(Decimal::zero);
+        old_total += old;
+        new_total += new;
 
-        table.add_row(vec![Cell::new(id), Cell::new(account)]);
+        table.add_row(vec![
+            Cell::new(item),
+            Cell::new(money(&old)),
+            Cell::new(money(&new)),
+            Cell::new(format!("{:+.2}", (new - old).round_dp(2))),
+            Cell::new(
+                percent_change(&old, &new)
+                    .map_or(String::new(), |p| format!("{:+.2}%", p.round_dp(2))),
+            ),
+            Cell::new(status),
+        ]);
     }
+    table.add_row(vec![
+        Cell::new("Total"),
+        Cell::new(money(&old_total)),
+        Cell::new(money(&new_total)),
+        Cell::new(format!("{:+.2}", (new_total - old_total).round_dp(2))),
+        Cell::new(
+            percent_change(&old_total, &new_total)
+                .map_or(String::new(), |p| format!("{:+.2}%", p.round_dp(2))),
+        ),
+        Cell::new(""),
+    ]);
 
-    table.to_string()
+    println!("{}", table);
+
+    Ok(())
 }
 
-pub fn account_values_to_table(rows: &Vec<Row>) -> String {
+/// Show what importing `plan` into the snapshot of `date` would do, and
+/// how the snapshot's total would compare with the previous snapshot
+pub fn import_preview(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    date: &NaiveDate,
+    plan: &[(Holding, Action)],
+) {
+    let optional = |amount: &Option<Decimal>| amount.map_or(String::new(), |a| money(&a));
+
     let mut table = comfy_table::Table::new();
     table
         .load_preset(ASCII_MARKDOWN)
         .set_content_arrangement(ContentArrangement::Dynamic)
-        .set_header(vec!["id", "Account", "Value"]);
+        .set_header(vec![
+            "Item",
+            "Quantity",
+            "Price",
+            "Value",
+            "Cost Basis",
+            "Action",
+        ]);
 
-    for row in rows {
-        let id: i32 = row.get(0);
-        let account: &str = row.get(1);
-        let value: Decimal = row.get(2);
+    for (holding, action) in plan {
+        table.add_row(vec![
+            Cell::new(&holding.item),
+            Cell::new(
+                holding
+                    .quantity
+                    .map_or(String::new(), |q| q.normalize().to_string()),
+            ),
+            Cell::new(optional(&holding.price)),
+            Cell::new(money(&holding.value)),
+            Cell::new(optional(&holding.cost)),
+            Cell::new(action),
+        ]);
+    }
+    println!("{}", table);
 
-        table.add_row(vec![Cell::new(id), Cell::new(account), Cell::new(value)]);
+    println!(
+        "Would import: {}. Nothing was written.",
+        csv::plan_summary(plan)
+    );
+
+    let change: Decimal = plan
+        .iter()
+        .map(|(holding, action)| match action {
+            Action::Insert => holding.value,
+            Action::Update(old) => holding.value - old,
+            Action::Remove => -holding.value,
+            _ => Decimal::zero(),
+        })
+        .sum();
+    let existing: Option<Decimal> = sql::get_portfolio_sum(pool.clone(), date).unwrap().get(2);
+    let total = existing.unwrap_or_else(Decimal::zero) + change;
+    println!("Total on {}: {}", date, money(&total));
+
+    if let Some(previous) = sql::get_portfolio_date(pool.clone(), &Some(date.pred())).unwrap() {
+        let previous_total = sql::get_portfolio_total_on(pool, &previous).unwrap();
+        println!(
+            "Previous snapshot on {}: {} (change {:+.2}{})",
+            previous,
+            money(&previous_total),
+            (total - previous_total).round_dp(2),
+            percent_change(&previous_total, &total)
+                .map_or(String::new(), |p| format!(", {:+.2}%", p.round_dp(2)))
+        );
     }
+}
 
-    table.to_string()
+/// Current and target share of one asset class
+#[derive(Debug, PartialEq)]
+struct Allocation {
+    class: String,
+    value: Decimal,
+    /// Share of the classes with a target, None for a class without one
+    current: Option<Decimal>,
+    target: Option<Decimal>,
+    /// Amount to buy (positive) or sell (negative) to reach the target
+    trade: Option<Decimal>,
 }
 
-fn expense_category_table(pool: r2d2::Pool<PostgresConnectionManager<NoTls>>) -> String {
-    let expense_vec: Vec<Row> = sql::get_expense_categories(pool.clone()).unwrap();
-    let expense_table_string = expense_category_rows_to_table(expense_vec);
-    expense_table_string
+/// Compare the value of each asset class with its target percentage.
+/// Classes without a target are listed but left out of the rebalancing:
+/// shares and trades are worked out from the value of the classes with a
+/// target, and the trades aim at the target share of that value after
+/// adding `contribution`. Classes with a target but no holdings are
+/// included.
+fn rebalance(
+    values: &[(String, Decimal)],
+    targets: &[(String, Option<Decimal>)],
+    contribution: &Decimal,
+) -> Vec<Allocation> {
+    let target_of = |class: &str| targets.iter().find(|t| t.0 == class).and_then(|t| t.1);
+    let current_total: Decimal = values
+        .iter()
+        .filter(|v| target_of(&v.0).is_some())
+        .map(|v| v.1)
+        .sum();
+    let total = current_total + contribution;
+
+    let mut classes: Vec<String> = targets.iter().map(|t| t.0.clone()).collect();
+    for (class, _) in values {
+        if !classes.contains(class) {
+            classes.push(class.clone());
+        }
+    }
+
+    classes
+        .into_iter()
+        .map(|class| {
+            let value = values
+                .iter()
+                .filter(|v| v.0 == class)
+                .map(|v| v.1)
+                .sum::<Decimal>();
+            let target = target_of(&class);
+            let hundred = Decimal::from(100);
+            let current = if current_total.is_zero() {
+                Decimal::zero()
+            } else {
+                value / current_total * hundred
+            };
+
+            Allocation {
+                current: target.map(|_| current),
+                trade: target.map(|target| total * target / hundred - value),
+                class,
+                value,
+                target,
+            }
+        })
+        .filter(|allocation| allocation.target.is_some() || !allocation.value.is_zero())
+        .collect()
 }
 
-pub fn add_expense_prompt(pool: r2d2::Pool<PostgresConnectionManager<NoTls>>) {
-    let date = user_input_date("Enter date");
+pub fn set_asset_class_target(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    class: &str,
+    target: &Decimal,
+) -> Result<(), String> {
+    if *target < Decimal::zero() || *target > Decimal::from(100) {
+        return Err(format!("Target must be between 0 and 100, got {}", target));
+    }
 
-    let table_vec: Vec<Row> = sql::get_account_ids(pool.clone()).unwrap();
-    let table_string = account_rows_to_table(table_vec);
-    println!("{}", table_string);
+    sql::set_asset_class_target(pool, class, target)
+        .map_err(|e| format!("Could not set target: {}", e))
+}
+
+/// Show how far each asset class has drifted from its target in the latest
+/// snapshot, and what to buy or sell to get back to it
+pub fn portfolio_rebalance(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    contribution: Option<Decimal>,
+) -> Result<(), String> {
+    let snapshot = portfolio_snapshot(pool.clone(), &None)?;
+    let values: Vec<(String, Decimal)> = sql::get_asset_class_values(pool.clone(), &snapshot)
+        .unwrap()
+        .iter()
+        .map(|row| {
+            let class: Option<String> = row.get(0);
+            (
+                class.unwrap_or_else(|| "Unassigned".to_string()),
+                row.get(1),
+            )
+        })
+        .collect();
+    let targets: Vec<(String, Option<Decimal>)> = sql::get_asset_class_targets(pool.clone())
+        .unwrap()
+        .iter()
+        .map(|row| (row.get(0), row.get(1)))
+        .collect();
 
-    let account_id = user_input_int("Enter ID");
+    let target_total: Decimal = targets.iter().filter_map(|t| t.1).sum();
+    if target_total != Decimal::from(100) {
+        eprintln!(
+            "Warning: targets add up to {}%, not 100%",
+            target_total.normalize()
+        );
+    }
 
-    let expense_input = user_input_float("Enter Amount");
-    let expense_value: Decimal = Decimal::from_str(&expense_input.to_string()).unwrap();
+    let contribution = contribution.unwrap_or_else(Decimal::zero);
+    let percent = |p: &Decimal| format!("{:.2}%", p.round_dp(2));
 
-    println!("{}", expense_category_table(pool.clone()));
-    let category_id = user_input_int("Enter number");
+    let mut table = comfy_table::Table::new();
+    table
+        .load_preset(ASCII_MARKDOWN)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(vec![
+            "Class", "Value", "Current", "Target", "Drift", "Buy/Sell",
+        ]);
 
-    let note = user_input_text("Note");
+    for allocation in rebalance(&values, &targets, &contribution) {
+        let (current, target, drift, trade) =
+            match (allocation.current, allocation.target, allocation.trade) {
+                (Some(current), Some(target), Some(trade)) => (
+                    percent(&current),
+                    percent(&target),
+                    format!("{:+.2}%", (current - target).round_dp(2)),
+                    format!("{:+.2}", trade.round_dp(2)),
+                ),
+                _ => (
+                    String::new(),
+                    "no target".to_string(),
+                    String::new(),
+                    String::new(),
+                ),
+            };
+        table.add_row(vec![
+            Cell::new(&allocation.class),
+            Cell::new(money(&allocation.value)),
+            Cell::new(current),
+            Cell::new(target),
+            Cell::new(drift),
+            Cell::new(trade),
+        ]);
+    }
 
-    sql::add_expense(
-        pool.clone(),
-        &date,
-        &account_id,
-        &expense_value,
-        &category_id,
-        note,
-    )
-    .expect("Could not add");
+    println!("Snapshot of {}", snapshot);
+    if !contribution.is_zero() {
+        println!("Including a contribution of {}", money(&contribution));
+    }
+    println!("{}", table);
+
+    Ok(())
+}
+
+/// Whether a subscription is renewing
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SubscriptionStatus {
+    Active,
+    Paused,
+    Cancelled,
 }
 
-pub fn add_subscription_prompt(pool: r2d2::Pool<PostgresConnectionManager<NoTls>>) {
-    let subscription_name = user_input_text("Subscription Name");
+impl SubscriptionStatus {
+    /// Check that a subscription with this status can change to `next`
+    fn check_transition(&self, next: &SubscriptionStatus) -> Result<(), String> {
+        use SubscriptionStatus::*;
+        match (self, next) {
+            (Active, Paused) | (Active, Cancelled) | (Paused, Cancelled) => Ok(()),
+            (Paused, Active) | (Cancelled, Active) => Ok(()),
+            _ if self == next => Err(format!("is already {}", self)),
+            _ => Err(format!("is {}, resume it before it can be {}", self, next)),
+        }
+    }
+}
 
-    println!("{}", expense_category_table(pool.clone()));
-    let category_id = user_input_int("Enter number");
+impl fmt::Display for SubscriptionStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let status = match self {
+            SubscriptionStatus::Active => "active",
+            SubscriptionStatus::Paused => "paused",
+            SubscriptionStatus::Cancelled => "cancelled",
+        };
+        write!(f, "{}", status)
+    }
+}
 
-    let price_input = user_input_float("Price");
-    let subscription_price: Decimal = Decimal::from_str(&price_input.to_string()).unwrap();
+impl FromStr for SubscriptionStatus {
+    type Err = String;
 
-    sql::add_subscription(
-        pool.clone(),
-        subscription_name,
-        category_id,
-        subscription_price,
-    )
-    .expect("Could not add");
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "active" => Ok(SubscriptionStatus::Active),
+            "paused" => Ok(SubscriptionStatus::Paused),
+            "cancelled" => Ok(SubscriptionStatus::Cancelled),
+            _ => Err(format!("expected active, paused or cancelled, got `{}`", s)),
+        }
+    }
 }
 
-pub fn update_account_values(pool: r2d2::Pool<PostgresConnectionManager<NoTls>>) {
-    let table_vec: Vec<Row> = sql::get_account_values(pool.clone()).unwrap();
-    let table_string = account_values_to_table(&table_vec);
-    println!("{}", table_string);
+/// A subscription as read from `sql::get_subscriptions`
+#[derive(Clone)]
+struct Subscription {
+    id: i32,
+    name: String,
+    category: String,
+    price: Decimal,
+    interval: BillingInterval,
+    start: Option<NaiveDate>,
+    account_id: Option<i32>,
+    category_id: i32,
+    status: SubscriptionStatus,
+    status_date: Option<NaiveDate>,
+    /// Date of the latest price change and the price before it, converted
+    /// to the current billing interval
+    last_change: Option<(NaiveDate, Decimal)>,
+}
 
-    let id = user_input_int("ID of Account to Update");
+impl Subscription {
+    fn from_row(row: &Row) -> Subscription {
+        let interval: &str = row.get(4);
+        let interval: BillingInterval = interval.parse().unwrap();
+        let changed_on: Option<NaiveDate> = row.get(10);
+        let old_price: Option<Decimal> = row.get(11);
+        let old_interval: Option<&str> = row.get(12);
+        let old_price = old_price.zip(old_interval).map(|(price, old_interval)| {
+            convert_price(&price, &old_interval.parse().unwrap(), &interval)
+        });
+        let status: &str = row.get(8);
+        Subscription {
+            id: row.get(0),
+            name: row.get(1),
+            category: row.get(2),
+            price: row.get(3),
+            interval,
+            start: row.get(5),
+            account_id: row.get(6),
+            category_id: row.get(7),
+            status: status.parse().unwrap(),
+            status_date: row.get(9),
+            last_change: changed_on.zip(old_price),
+        }
+    }
 
-    let value = user_input_float("New Value");
-    let value_decimal = Decimal::from_f64(value).unwrap();
+    fn is_active(&self) -> bool {
+        self.status == SubscriptionStatus::Active
+    }
 
-    let rows_updated =
-        sql::update_account_value(pool.clone(), &value_decimal, &id).expect("Problem Updating");
+    /// Price spread over a whole year
+    fn yearly(&self) -> Decimal {
+        self.price * Decimal::from(self.interval.per_year())
+    }
 
-    println!("{} rows updated", rows_updated);
+    /// First and last date of each stretch the subscription was active, up
+    /// to `today`, given its status history (oldest first). Renewals in
+    /// these ranges are posted as expenses; the time it was paused or
+    /// cancelled is not charged.
+    fn active_ranges(
+        &self,
+        changes: &[(NaiveDate, SubscriptionStatus)],
+        today: &NaiveDate,
+    ) -> Vec<(NaiveDate, NaiveDate)> {
+        let start = match self.start {
+            Some(start) => start,
+            None => return Vec::new(),
+        };
 
-    let table_vec: Vec<Row> = sql::get_account_values(pool.clone()).unwrap();
-    let table_string = account_values_to_table(&table_vec);
-    println!("{}", table_string);
+        // a history that begins with a resume was started after the
+        // subscription had been stopped
+        let mut active_since = match changes.first() {
+            Some((_, SubscriptionStatus::Active)) => None,
+            _ => Some(start),
+        };
+        let mut ranges = Vec::new();
+        for (date, status) in changes {
+            match (active_since, status) {
+                (Some(since), SubscriptionStatus::Paused)
+                | (Some(since), SubscriptionStatus::Cancelled) => {
+                    ranges.push((since, date.pred()));
+                    active_since = None;
+                }
+                (None, SubscriptionStatus::Active) => active_since = Some(*date),
+                _ => {}
+            }
+        }
+        if let Some(since) = active_since {
+            ranges.push((since, *today));
+        }
+
+        ranges
+            .into_iter()
+            .map(|(first, last)| (first.max(start), last.min(*today)))
+            .filter(|(first, last)| first <= last)
+            .collect()
+    }
 }
 
-pub fn account_types_to_table(rows: &Vec<Row>) -> String {
+/// A price charged every `from` as the same cost charged every `to`
+fn convert_price(price: &Decimal, from: &BillingInterval, to: &BillingInterval) -> Decimal {
+    price * Decimal::from(from.per_year()) / Decimal::from(to.per_year())
+}
+
+/// Price in effect on `date`: the price replaced by the first change after
+/// it, or the `current` price if it has not changed since. `changes` holds
+/// the date of each change and the price before it, oldest first.
+fn price_on(current: &Decimal, changes: &[(NaiveDate, Decimal)], date: &NaiveDate) -> Decimal {
+    changes
+        .iter()
+        .find(|(changed_on, _)| changed_on > date)
+        .map_or(*current, |(_, old_price)| *old_price)
+}
+
+/// Subscriptions with their cost normalised to a year and a month, most
+/// expensive first, followed by a total
+pub fn subscription_rows_to_table(rows: Vec<Row>, today: &NaiveDate) -> String {
     let mut table = comfy_table::Table::new();
     table
         .load_preset(ASCII_MARKDOWN)
         .set_content_arrangement(ContentArrangement::Dynamic)
-        .set_header(vec!["id", "Account Type"]);
+        .set_header(vec![
+            "id",
+            "Name",
+            "Category",
+            "Price",
+            "Every",
+            "Yearly",
+            "Monthly",
+            "Next Renewal",
+            "Status",
+            "Price Change",
+        ]);
+
+    let mut subscriptions: Vec<Subscription> = rows.iter().map(Subscription::from_row).collect();
+    subscriptions.sort_by_key(|s| std::cmp::Reverse(s.yearly()));
+
+    let mut total = Decimal::zero();
+    for subscription in &subscriptions {
+        let yearly = subscription.yearly();
+        let mut next_renewal = String::new();
+        let mut status = subscription.status.to_string();
+        if subscription.is_active() {
+            total += yearly;
+            if let Some(start) = subscription.start {
+                next_renewal = subscription
+                    .interval
+                    .next_renewal(&start, today)
+                    .to_string();
+            }
+        } else if let Some(date) = subscription.status_date {
+            status = format!("{} {}", status, date);
+        }
+        let price_change =
+            subscription
+                .last_change
+                .map_or(String::new(), |(changed_on, old_price)| {
+                    format!(
+                        "{:+.2} on {}",
+                        (subscription.price - old_price).round_dp(2),
+                        changed_on
+                    )
+                });
+
+        table.add_row(vec![
+            Cell::new(subscription.id),
+            Cell::new(&subscription.name),
+            Cell::new(&subscription.category),
+            Cell::new(money(&subscription.price)),
+            Cell::new(subscription.interval),
+            Cell::new(money(&yearly)),
+            Cell::new(money(&(yearly / Decimal::from(12)))),
+            Cell::new(next_renewal),
+            Cell::new(status),
+            Cell::new(price_change),
+        ]);
+    }
+    table.add_row(vec![
+        Cell::new(""),
+        Cell::new("Total"),
+        Cell::new(""),
+        Cell::new(""),
+        Cell::new(""),
+        Cell::new(money(&total)),
+        Cell::new(money(&(total / Decimal::from(12)))),
+        Cell::new(""),
+        Cell::new(""),
+        Cell::new(""),
+    ]);
+
+    table.to_string()
+}
+
+/// Renewals due within the next `days` days, in date order
+pub fn upcoming_subscriptions(pool: r2d2::Pool<PostgresConnectionManager<NoTls>>, days: i64) {
+    let rows: Vec<Row> = sql::get_subscriptions(pool, false).unwrap();
+    let today = Local::today().naive_local();
+    let until = today + chrono::Duration::days(days);
+
+    let mut renewals: Vec<(NaiveDate, Subscription)> = Vec::new();
+    for subscription in rows.iter().map(Subscription::from_row) {
+        if let Some(start) = subscription.start {
+            for date in subscription
+                .interval
+                .renewals_between(&start, &today, &until)
+            {
+                renewals.push((date, subscription.clone()));
+            }
+        }
+    }
+    renewals.sort_by(|a, b| (a.0, &a.1.name).cmp(&(b.0, &b.1.name)));
+
+    let mut table = comfy_table::Table::new();
+    table
+        .load_preset(ASCII_MARKDOWN)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(vec!["Date", "id", "Name", "Category", "Price"]);
+
+    let mut total = Decimal::zero();
+    for (date, subscription) in &renewals {
+        total += subscription.price;
+        table.add_row(vec![
+            Cell::new(date),
+            Cell::new(subscription.id),
+            Cell::new(&subscription.name),
+            Cell::new(&subscription.category),
+            Cell::new(money(&subscription.price)),
+        ]);
+    }
+    table.add_row(vec![
+        Cell::new(""),
+        Cell::new(""),
+        Cell::new("Total"),
+        Cell::new(""),
+        Cell::new(money(&total)),
+    ]);
+
+    println!("{}", table);
+}
+
+/// Write a recurring calendar event for each active subscription to
+/// `output`, or print it if no file is given
+pub fn export_subscriptions_ical(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    output: Option<String>,
+) -> Result<(), String> {
+    let rows: Vec<Row> = sql::get_subscriptions(pool, false).unwrap();
+
+    let mut events = Vec::new();
+    for subscription in rows.iter().map(Subscription::from_row) {
+        let start = match subscription.start {
+            Some(start) => start,
+            None => {
+                eprintln!("Skipping {}: no start date", subscription.name);
+                continue;
+            }
+        };
+        // a resumed subscription's events start again from its resume date
+        let first = match subscription.status_date {
+            Some(resumed) => subscription.interval.next_renewal(&start, &resumed),
+            None => start,
+        };
+
+        events.push(ical::Event {
+            uid: format!("subscription-{}@haibun", subscription.id),
+            summary: format!("{} renewal", subscription.name),
+            description: format!(
+                "Category: {}\nPrice: {} every {}",
+                subscription.category,
+                money(&subscription.price),
+                subscription.interval
+            ),
+            start: first,
+            rrule: ical::rrule(&subscription.interval, &start),
+        });
+    }
+
+    let stamp = chrono::Utc::now().naive_utc();
+    let calendar = ical::calendar(&events, &stamp);
+
+    match output {
+        Some(path) => {
+            std::fs::write(&path, calendar)
+                .map_err(|e| format!("Could not write {}: {}", path, e))?;
+            println!("Wrote {} subscriptions to {}", events.len(), path);
+        }
+        None => print!("{}", calendar),
+    }
+
+    Ok(())
+}
+
+pub fn account_rows_to_table(rows: Vec<Row>) -> String {
+    let mut table = comfy_table::Table::new();
+    table
+        .load_preset(ASCII_MARKDOWN)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(vec!["id", "Account"]);
 
     for row in rows {
         let id: i32 = row.get(0);
-        let account_type: &str = row.get(1);
+        let account: &str = row.get(1);
 
-        table.add_row(vec![Cell::new(id), Cell::new(account_type)]);
+        table.add_row(vec![Cell::new(id), Cell::new(account)]);
     }
 
     table.to_string()
 }
 
-pub fn add_account_prompt(pool: r2d2::Pool<PostgresConnectionManager<NoTls>>) {
-
-    let account_name = user_input_text("Account Name");
+pub fn account_values_to_table(rows: &Vec<Row>) -> String {
+    let mut table = comfy_table::Table::new();
+    table
+        .load_preset(ASCII_MARKDOWN)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(vec!["id", "Account", "Value"]);
 
-    println!("{}", account_type_table(pool.clone()));
-    let account_type_id = user_input_int("Enter number");
+    for row in rows {
+        let id: i32 = row.get(0);
+        let account: &str = row.get(1);
+        let value: Decimal = row.get(2);
 
-    let value_input = user_input_float("Account Value");
-    let account_value: Decimal = Decimal::from_str(&value_input.to_string()).unwrap();
+        table.add_row(vec![Cell::new(id), Cell::new(account), Cell::new(value)]);
+    }
 
-    sql::add_account(
-        pool.clone(),
-        account_name,
-        account_type_id,
-        account_value,
-    )
-    .expect("Could not add");
+    table.to_string()
 }
 
-fn account_type_table(pool: r2d2::Pool<PostgresConnectionManager<NoTls>>) -> String {
+fn expense_category_table(pool: r2d2::Pool<PostgresConnectionManager<NoTls>>) -> String {
+    let expense_vec: Vec<Row> = sql::get_expense_categories(pool.clone()).unwrap();
+    expense_category_rows_to_table(expense_vec)
+}
+
+/// Whether missing values can be asked for interactively
+fn can_prompt() -> bool {
+    std::io::stdin().is_terminal()
+}
+
+fn require_prompt(flag: &str) -> Result<(), String> {
+    if can_prompt() {
+        Ok(())
+    } else {
+        Err(format!("Missing --{} (no terminal to prompt on)", flag))
+    }
+}
+
+fn resolve_account(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    account: &str,
+) -> Result<i32, String> {
+    sql::find_account_id(pool, account)
+        .map_err(|e| format!("Could not look up account: {}", e))?
+        .ok_or(format!("No account named or numbered `{}`", account))
+}
+
+fn resolve_category(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    category: &str,
+) -> Result<i32, String> {
+    sql::find_category_id(pool, category)
+        .map_err(|e| format!("Could not look up category: {}", e))?
+        .ok_or(format!("No category named or numbered `{}`", category))
+}
+
+fn resolve_subscription(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    subscription: &str,
+) -> Result<i32, String> {
+    sql::find_subscription_id(pool, subscription)
+        .map_err(|e| format!("Could not look up subscription: {}", e))?
+        .ok_or(format!(
+            "No subscription named or numbered `{}`",
+            subscription
+        ))
+}
+
+fn check_amount(amount: &Decimal) -> Result<(), String> {
+    if *amount <= Decimal::zero() {
+        return Err(format!("Amount must be positive, got {}", amount));
+    }
+    Ok(())
+}
+
+/// Add an expense from the given values, prompting only for the ones left out
+pub fn add_expense_prompt(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    date: Option<NaiveDate>,
+    account: Option<String>,
+    amount: Option<Decimal>,
+    category: Option<String>,
+    note: Option<String>,
+) -> Result<(), String> {
+    let date = match date {
+        Some(date) => date,
+        None => {
+            require_prompt("date")?;
+            user_input_date("Enter date")
+        }
+    };
+
+    let account_id = match account {
+        Some(account) => resolve_account(pool.clone(), &account)?,
+        None => {
+            require_prompt("account")?;
+            let table_vec: Vec<Row> = sql::get_account_ids(pool.clone()).unwrap();
+            let table_string = account_rows_to_table(table_vec);
+            println!("{}", table_string);
+
+            user_input_int("Enter ID")
+        }
+    };
+
+    let expense_value = match amount {
+        Some(amount) => amount,
+        None => {
+            require_prompt("amount")?;
+            let expense_input = user_input_float("Enter Amount");
+            Decimal::from_str(&expense_input.to_string()).unwrap()
+        }
+    };
+    check_amount(&expense_value)?;
+
+    let category_id = match category {
+        Some(category) => resolve_category(pool.clone(), &category)?,
+        None => {
+            require_prompt("category")?;
+            println!("{}", expense_category_table(pool.clone()));
+            user_input_int("Enter number")
+        }
+    };
+
+    let note = match note {
+        Some(note) => note,
+        None if can_prompt() => user_input_text("Note"),
+        None => String::new(),
+    };
+
+    sql::add_expense(
+        pool.clone(),
+        &date,
+        &account_id,
+        &expense_value,
+        &category_id,
+        note,
+    )
+    .map_err(|e| format!("Could not add expense: {}", e))?;
+
+    warn_if_over_budget(pool.clone(), &category_id, &date);
+
+    Ok(())
+}
+
+/// Edit an expense. Flags replace individual fields; with no flags every
+/// field is prompted for, pre-filled with its current value.
+pub fn edit_expense(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    id: i32,
+    date: Option<NaiveDate>,
+    account: Option<String>,
+    amount: Option<Decimal>,
+    category: Option<String>,
+    note: Option<String>,
+) -> Result<(), String> {
+    let current = sql::get_expense_values(pool.clone(), &id)
+        .unwrap()
+        .ok_or(format!("No expense with id {}", id))?;
+    // rows added outside haibun can leave any of these empty
+    let current_date: Option<NaiveDate> = current.get(0);
+    let current_account: Option<i32> = current.get(1);
+    let current_amount: Option<Decimal> = current.get(2);
+    let current_category: Option<i32> = current.get(3);
+    let current_note: String = current.get(4);
+
+    println!(
+        "{}",
+        expense_rows_to_table(sql::get_expense(pool.clone(), &id).unwrap())
+    );
+
+    let prompt = date.is_none()
+        && account.is_none()
+        && amount.is_none()
+        && category.is_none()
+        && note.is_none();
+    if prompt {
+        require_prompt("date, --account, --amount, --category or --note")?;
+    }
+
+    let date = match (date, current_date) {
+        (Some(date), _) => date,
+        (None, Some(current)) if !prompt => current,
+        (None, current) => {
+            require_prompt("date")?;
+            user_input_current("Date", current)
+        }
+    };
+
+    let account_id = match (account, current_account) {
+        (Some(account), _) => resolve_account(pool.clone(), &account)?,
+        (None, Some(current)) if !prompt => current,
+        (None, current) => {
+            require_prompt("account")?;
+            let table_vec: Vec<Row> = sql::get_account_ids(pool.clone()).unwrap();
+            println!("{}", account_rows_to_table(table_vec));
+            user_input_current("Account ID", current)
+        }
+    };
+
+    let expense_value = match (amount, current_amount) {
+        (Some(amount), _) => amount,
+        (None, Some(current)) if !prompt => current,
+        (None, current) => {
+            require_prompt("amount")?;
+            user_input_current("Amount", current)
+        }
+    };
+    check_amount(&expense_value)?;
+
+    let category_id = match (category, current_category) {
+        (Some(category), _) => resolve_category(pool.clone(), &category)?,
+        (None, Some(current)) if !prompt => current,
+        (None, current) => {
+            require_prompt("category")?;
+            println!("{}", expense_category_table(pool.clone()));
+            user_input_current("Category ID", current)
+        }
+    };
+
+    let note = match note {
+        Some(note) => note,
+        None if prompt => user_input_default("Note", current_note),
+        None => current_note,
+    };
+
+    sql::update_expense(
+        pool.clone(),
+        &id,
+        &date,
+        &account_id,
+        &expense_value,
+        &category_id,
+        note,
+    )
+    .map_err(|e| format!("Could not update expense: {}", e))?;
+
+    println!(
+        "{}",
+        expense_rows_to_table(sql::get_expense(pool.clone(), &id).unwrap())
+    );
+    warn_if_over_budget(pool.clone(), &category_id, &date);
+
+    Ok(())
+}
+
+/// Delete an expense after showing it and asking for confirmation
+pub fn delete_expense(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    id: i32,
+    skip_confirm: bool,
+) -> Result<(), String> {
+    let rows = sql::get_expense(pool.clone(), &id).unwrap();
+    if rows.is_empty() {
+        return Err(format!("No expense with id {}", id));
+    }
+    println!("{}", expense_rows_to_table(rows));
+
+    if !skip_confirm {
+        require_prompt("yes")?;
+        if !user_input_confirm("Delete this expense?") {
+            return Ok(());
+        }
+    }
+
+    let rows_deleted = sql::delete_expense(pool.clone(), &id)
+        .map_err(|e| format!("Could not delete expense: {}", e))?;
+    println!("{} rows deleted", rows_deleted);
+
+    Ok(())
+}
+
+fn show_subscription(pool: r2d2::Pool<PostgresConnectionManager<NoTls>>, id: &i32) {
+    let rows: Vec<Row> = sql::get_subscriptions(pool, true)
+        .unwrap()
+        .into_iter()
+        .filter(|row| row.get::<_, i32>(0) == *id)
+        .collect();
+    let today = Local::today().naive_local();
+    println!("{}", subscription_rows_to_table(rows, &today));
+}
+
+/// Edit a subscription. Flags replace individual fields; with no flags every
+/// field is prompted for, pre-filled with its current value. A new price is
+/// recorded in the subscription's price history.
+#[allow(clippy::too_many_arguments)]
+pub fn edit_subscription(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    subscription: &str,
+    name: Option<String>,
+    category: Option<String>,
+    price: Option<Decimal>,
+    every: Option<BillingInterval>,
+    start: Option<NaiveDate>,
+    account: Option<String>,
+) -> Result<(), String> {
+    let id = resolve_subscription(pool.clone(), subscription)?;
+    let current = sql::get_subscription_values(pool.clone(), &id)
+        .unwrap()
+        .ok_or(format!("No subscription with id {}", id))?;
+    let current_name: String = current.get(0);
+    let current_category: i32 = current.get(1);
+    let current_price: Decimal = current.get(2);
+    let current_interval: &str = current.get(3);
+    let current_interval: BillingInterval = current_interval.parse()?;
+    let current_start: Option<NaiveDate> = current.get(4);
+    let current_account: Option<i32> = current.get(5);
+
+    show_subscription(pool.clone(), &id);
+
+    let prompt = name.is_none()
+        && category.is_none()
+        && price.is_none()
+        && every.is_none()
+        && start.is_none()
+        && account.is_none();
+    if prompt {
+        require_prompt("name, --category, --price, --every, --start or --account")?;
+    }
+
+    let subscription_name = match name {
+        Some(name) => name,
+        None if prompt => user_input_default("Subscription Name", current_name),
+        None => current_name,
+    };
+
+    let category_id = match category {
+        Some(category) => resolve_category(pool.clone(), &category)?,
+        None if prompt => {
+            println!("{}", expense_category_table(pool.clone()));
+            user_input_default("Category ID", current_category)
+        }
+        None => current_category,
+    };
+
+    let subscription_price = match price {
+        Some(price) => price,
+        None if prompt => user_input_default("Price per billing interval", current_price),
+        None => current_price,
+    };
+    check_amount(&subscription_price)?;
+
+    let billing_interval = match every {
+        Some(every) => every,
+        None if prompt => user_input_default(
+            "Billed every (week, month, quarter or year)",
+            current_interval,
+        ),
+        None => current_interval,
+    };
+
+    let start_date = match start {
+        Some(start) => Some(start),
+        None if prompt => Some(user_input_default(
+            "First charged on",
+            current_start.unwrap_or_else(|| Local::today().naive_local()),
+        )),
+        None => current_start,
+    };
+
+    let account_id = match account {
+        Some(account) => Some(resolve_account(pool.clone(), &account)?),
+        None if prompt => {
+            let table_vec: Vec<Row> = sql::get_account_ids(pool.clone()).unwrap();
+            println!("{}", account_rows_to_table(table_vec));
+            match user_input_default("Account ID (0 for none)", current_account.unwrap_or(0)) {
+                0 => None,
+                id => Some(id),
+            }
+        }
+        None => current_account,
+    };
+
+    sql::update_subscription(
+        pool.clone(),
+        &id,
+        &subscription_name,
+        &category_id,
+        &subscription_price,
+        &billing_interval,
+        &start_date,
+        &account_id,
+    )
+    .map_err(|e| format!("Could not update subscription: {}", e))?;
+
+    show_subscription(pool.clone(), &id);
+
+    Ok(())
+}
+
+/// Pause, resume or cancel a subscription from `date` (default today)
+pub fn set_subscription_status(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    subscription: &str,
+    status: SubscriptionStatus,
+    date: Option<NaiveDate>,
+) -> Result<(), String> {
+    let id = resolve_subscription(pool.clone(), subscription)?;
+    let date = date.unwrap_or_else(|| Local::today().naive_local());
+
+    let current = sql::get_subscription_values(pool.clone(), &id)
+        .map_err(|e| format!("Could not read subscription: {}", e))?
+        .ok_or(format!("No subscription with id {}", id))?;
+    let name: String = current.get(0);
+    let current_status: &str = current.get(6);
+    let current_status: SubscriptionStatus = current_status.parse()?;
+    let status_date: Option<NaiveDate> = current.get(7);
+
+    current_status
+        .check_transition(&status)
+        .map_err(|e| format!("{} {}", name, e))?;
+    if let Some(status_date) = status_date {
+        if date < status_date {
+            return Err(format!(
+                "{} was last changed on {}, so it cannot be {} from {}",
+                name, status_date, status, date
+            ));
+        }
+    }
+
+    sql::set_subscription_status(pool.clone(), &id, &status.to_string(), &date)
+        .map_err(|e| format!("Could not update subscription: {}", e))?;
+
+    show_subscription(pool.clone(), &id);
+
+    Ok(())
+}
+
+/// Delete a subscription after showing it and asking for confirmation
+pub fn delete_subscription(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    subscription: &str,
+    skip_confirm: bool,
+) -> Result<(), String> {
+    let id = resolve_subscription(pool.clone(), subscription)?;
+    show_subscription(pool.clone(), &id);
+
+    if !skip_confirm {
+        require_prompt("yes")?;
+        if !user_input_confirm("Delete this subscription? Expenses already posted are kept") {
+            return Ok(());
+        }
+    }
+
+    let rows_deleted = sql::delete_subscription(pool.clone(), &id)
+        .map_err(|e| format!("Could not delete subscription: {}", e))?;
+    println!("{} rows deleted", rows_deleted);
+
+    Ok(())
+}
+
+/// Add an expense for every renewal up to today that has not been posted
+/// yet. Safe to run repeatedly: posted renewals are recorded and skipped.
+pub fn post_subscriptions(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    since: Option<NaiveDate>,
+) -> Result<(), String> {
+    let rows: Vec<Row> = sql::get_subscriptions(pool.clone(), true).unwrap();
+    let today = Local::today().naive_local();
+
+    // without --since, start where the last run left off, so the first run
+    // does not back-date every renewal since a subscription started
+    let since = match since {
+        Some(since) => since,
+        None => sql::get_last_post_run(pool.clone())
+            .map_err(|e| format!("Could not read the last posting run: {}", e))?
+            .unwrap_or(today),
+    };
+    println!("Posting renewals from {} to {}", since, today);
+
+    let mut table = comfy_table::Table::new();
+    table
+        .load_preset(ASCII_MARKDOWN)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(vec!["Expense id", "Date", "Name", "Amount"]);
+
+    let mut posted = 0;
+    for subscription in rows.iter().map(Subscription::from_row) {
+        let (start, account_id) = match (subscription.start, subscription.account_id) {
+            (Some(start), Some(account_id)) => (start, account_id),
+            (None, _) => {
+                eprintln!("Skipping {}: no start date", subscription.name);
+                continue;
+            }
+            (_, None) => {
+                eprintln!("Skipping {}: no account to post to", subscription.name);
+                continue;
+            }
+        };
+        let status_changes: Vec<(NaiveDate, SubscriptionStatus)> =
+            sql::get_subscription_status_changes(pool.clone(), &subscription.id)
+                .map_err(|e| format!("Could not read status history: {}", e))?
+                .iter()
+                .map(|row| {
+                    let status: &str = row.get(1);
+                    (row.get(0), status.parse().unwrap())
+                })
+                .collect();
+
+        let changes: Vec<(NaiveDate, Decimal)> =
+            sql::get_subscription_price_changes(pool.clone(), &subscription.id)
+                .map_err(|e| format!("Could not read price changes: {}", e))?
+                .iter()
+                .map(|row| {
+                    let old_interval: &str = row.get(2);
+                    let old_price = convert_price(
+                        &row.get(1),
+                        &old_interval.parse().unwrap(),
+                        &subscription.interval,
+                    );
+                    (row.get(0), old_price)
+                })
+                .collect();
+        let already_posted = sql::get_posted_renewals(pool.clone(), &subscription.id)
+            .map_err(|e| format!("Could not read posted renewals: {}", e))?;
+        let due = subscription
+            .active_ranges(&status_changes, &today)
+            .into_iter()
+            .filter(|(_, last)| *last >= since)
+            .flat_map(|(first, last)| {
+                subscription
+                    .interval
+                    .renewals_between(&start, &first.max(since), &last)
+            })
+            .filter(|date| !already_posted.contains(date));
+
+        for date in due {
+            let price = price_on(&subscription.price, &changes, &date);
+            let expense_id = sql::post_subscription_renewal(
+                pool.clone(),
+                &subscription.id,
+                &date,
+                &account_id,
+                &price,
+                &subscription.category_id,
+                &subscription.name,
+            )
+            .map_err(|e| format!("Could not post {}: {}", subscription.name, e))?;
+
+            if let Some(expense_id) = expense_id {
+                posted += 1;
+                table.add_row(vec![
+                    Cell::new(expense_id),
+                    Cell::new(date),
+                    Cell::new(&subscription.name),
+                    Cell::new(money(&price)),
+                ]);
+            }
+        }
+    }
+    sql::record_post_run(pool.clone(), &today)
+        .map_err(|e| format!("Could not record the posting run: {}", e))?;
+
+    if posted == 0 {
+        println!("No renewals to post");
+    } else {
+        println!("{}", table);
+        println!("Posted {} renewals", posted);
+    }
+
+    Ok(())
+}
+
+pub fn add_subscription_prompt(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    name: Option<String>,
+    category: Option<String>,
+    price: Option<Decimal>,
+    every: Option<BillingInterval>,
+    start: Option<NaiveDate>,
+    account: Option<String>,
+) -> Result<(), String> {
+    let subscription_name = match name {
+        Some(name) => name,
+        None => {
+            require_prompt("name")?;
+            user_input_text("Subscription Name")
+        }
+    };
+
+    let category_id = match category {
+        Some(category) => resolve_category(pool.clone(), &category)?,
+        None => {
+            require_prompt("category")?;
+            println!("{}", expense_category_table(pool.clone()));
+            user_input_int("Enter number")
+        }
+    };
+
+    let subscription_price = match price {
+        Some(price) => price,
+        None => {
+            require_prompt("price")?;
+            let price_input = user_input_float("Price per billing interval");
+            Decimal::from_str(&price_input.to_string()).unwrap()
+        }
+    };
+    check_amount(&subscription_price)?;
+
+    let billing_interval = match every {
+        Some(every) => every,
+        None if can_prompt() => user_input_default(
+            "Billed every (week, month, quarter or year)",
+            BillingInterval::Month,
+        ),
+        None => BillingInterval::Month,
+    };
+
+    let today = Local::today().naive_local();
+    let start_date = match start {
+        Some(start) => start,
+        None if can_prompt() => user_input_default("First charged on", today),
+        None => today,
+    };
+
+    let account = match account {
+        Some(account) => Some(account),
+        None if can_prompt() => {
+            let table_vec: Vec<Row> = sql::get_account_ids(pool.clone()).unwrap();
+            println!("{}", account_rows_to_table(table_vec));
+            user_input_optional("Account renewals are paid from (blank for none)")
+        }
+        None => None,
+    };
+    let account_id = match account {
+        Some(account) => Some(resolve_account(pool.clone(), &account)?),
+        None => None,
+    };
+
+    sql::add_subscription(
+        pool.clone(),
+        subscription_name,
+        category_id,
+        subscription_price,
+        &billing_interval,
+        &start_date,
+        &account_id,
+    )
+    .map_err(|e| format!("Could not add subscription: {}", e))?;
+
+    Ok(())
+}
+
+pub fn update_account_values(pool: r2d2::Pool<PostgresConnectionManager<NoTls>>) {
+    let table_vec: Vec<Row> = sql::get_account_values(pool.clone()).unwrap();
+    let table_string = account_values_to_table(&table_vec);
+    println!("{}", table_string);
+
+    let id = user_input_int("ID of Account to Update");
+
+    let value = user_input_float("New Value");
+    let value_decimal = Decimal::from_f64(value).unwrap();
+
+    let rows_updated =
+        sql::update_account_value(pool.clone(), &value_decimal, &id).expect("Problem Updating");
+
+    println!("{} rows updated", rows_updated);
+
+    let table_vec: Vec<Row> = sql::get_account_values(pool.clone()).unwrap();
+    let table_string = account_values_to_table(&table_vec);
+    println!("{}", table_string);
+}
+
+pub fn account_types_to_table(rows: &Vec<Row>) -> String {
+    let mut table = comfy_table::Table::new();
+    table
+        .load_preset(ASCII_MARKDOWN)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(vec!["id", "Account Type"]);
+
+    for row in rows {
+        let id: i32 = row.get(0);
+        let account_type: &str = row.get(1);
+
+        table.add_row(vec![Cell::new(id), Cell::new(account_type)]);
+    }
+
+    table.to_string()
+}
+
+pub fn add_account_prompt(pool: r2d2::Pool<PostgresConnectionManager<NoTls>>) {
+
+    let account_name = user_input_text("Account Name");
+
+    println!("{}", account_type_table(pool.clone()));
+    let account_type_id = user_input_int("Enter number");
+
+    let value_input = user_input_float("Account Value");
+    let account_value: Decimal = Decimal::from_str(&value_input.to_string()).unwrap();
+
+    sql::add_account(
+        pool.clone(),
+        account_name,
+        account_type_id,
+        account_value,
+    )
+    .expect("Could not add");
+}
+
+fn account_type_table(pool: r2d2::Pool<PostgresConnectionManager<NoTls>>) -> String {
     let account_type_vec: Vec<Row> = sql::get_account_types(pool.clone()).unwrap();
-    let table_string = account_types_to_table(&account_type_vec);
-    table_string
+    account_types_to_table(&account_type_vec)
+}
+
+/// Spending against a category's budget for one period
+struct BudgetStatus {
+    category: String,
+    period: Period,
+    start: NaiveDate,
+    limit: Decimal,
+    spent: Decimal,
+}
+
+/// Number of whole periods from the one containing `first` up to the one
+/// starting on `start`
+fn periods_between(period: &Period, first: &NaiveDate, start: &NaiveDate) -> u32 {
+    let mut count = 0;
+    let mut current = period.start_of(first);
+    while current < *start {
+        current = period.next(&current);
+        count += 1;
+    }
+    count
+}
+
+/// Work out the budget status for the period containing `date`. With
+/// rollover, whatever was left (or overspent) in every earlier period since
+/// the budget started is added to this period's limit.
+fn budget_status(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    budget: &Row,
+    date: &NaiveDate,
+) -> BudgetStatus {
+    let category_id: i32 = budget.get(0);
+    let category: String = budget.get(1);
+    let period: &str = budget.get(2);
+    let period = Period::from_str(period).unwrap();
+    let amount: Decimal = budget.get(3);
+    let rollover: bool = budget.get(4);
+    let start_date: NaiveDate = budget.get(5);
+
+    let start = period.start_of(date);
+    let end = period.next(&start);
+    let spent = sql::get_category_spent(pool.clone(), &category_id, &start, &end).unwrap();
+
+    let mut limit = amount;
+    let earlier = periods_between(&period, &start_date, &start);
+    if rollover && earlier > 0 {
+        let first = period.start_of(&start_date);
+        let earlier_spent =
+            sql::get_category_spent(pool.clone(), &category_id, &first, &start).unwrap();
+        limit += amount * Decimal::from(earlier) - earlier_spent;
+    }
+
+    BudgetStatus {
+        category,
+        period,
+        start,
+        limit,
+        spent,
+    }
+}
+
+fn budget_statuses_to_table(statuses: &[BudgetStatus]) -> String {
+    let mut table = comfy_table::Table::new();
+    table
+        .load_preset(ASCII_MARKDOWN)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(vec![
+            "Category",
+            "Period",
+            "Budget",
+            "Spent",
+            "Remaining",
+            "Used",
+        ]);
+
+    for status in statuses {
+        let used = if status.limit > Decimal::zero() {
+            format!("{:.0}%", status.spent / status.limit * Decimal::from(100))
+        } else {
+            "".to_string()
+        };
+
+        table.add_row(vec![
+            Cell::new(&status.category),
+            Cell::new(status.period.label(&status.start)),
+            Cell::new(money(&status.limit)),
+            Cell::new(money(&status.spent)),
+            Cell::new(money(&(status.limit - status.spent))),
+            Cell::new(used),
+        ]);
+    }
+
+    table.to_string()
+}
+
+/// Show spending against every budget for the periods containing `date`
+pub fn budget_view(pool: r2d2::Pool<PostgresConnectionManager<NoTls>>, date: &NaiveDate) {
+    let budgets: Vec<Row> = sql::get_budgets(pool.clone()).unwrap();
+    let statuses: Vec<BudgetStatus> = budgets
+        .iter()
+        .map(|budget| budget_status(pool.clone(), budget, date))
+        .collect();
+
+    println!("{}", budget_statuses_to_table(&statuses));
+}
+
+pub fn set_budget(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    category: &str,
+    amount: &Decimal,
+    period: &Period,
+    rollover: bool,
+) -> Result<(), String> {
+    let category_id = resolve_category(pool.clone(), category)?;
+    check_amount(amount)?;
+
+    let today = Local::today().naive_local();
+    sql::set_budget(
+        pool.clone(),
+        &category_id,
+        period,
+        amount,
+        rollover,
+        &period.start_of(&today),
+    )
+    .map_err(|e| format!("Could not set budget: {}", e))
+}
+
+/// Print a warning if the category of an expense is over budget for the
+/// period the expense falls in
+fn warn_if_over_budget(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    category_id: &i32,
+    date: &NaiveDate,
+) {
+    if let Some(budget) = sql::get_budget(pool.clone(), category_id).unwrap() {
+        let status = budget_status(pool.clone(), &budget, date);
+        // periods before the budget was set are not held to it
+        let start_date: NaiveDate = budget.get(5);
+        if status.start < status.period.start_of(&start_date) {
+            return;
+        }
+        if status.spent > status.limit {
+            println!(
+                "Warning: {} is over budget for {} by {} (spent {} of {})",
+                status.category,
+                status.period.label(&status.start),
+                money(&(status.spent - status.limit)),
+                money(&status.spent),
+                money(&status.limit)
+            );
+        }
+    }
+}
+
+pub fn category_view(pool: r2d2::Pool<PostgresConnectionManager<NoTls>>) {
+    println!("{}", expense_category_table(pool.clone()));
+}
+
+pub fn add_category(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    category_name: &str,
+    parent: Option<String>,
+) -> Result<(), String> {
+    let parent_id = match parent {
+        Some(parent) => Some(resolve_category(pool.clone(), &parent)?),
+        None => None,
+    };
+
+    sql::add_category(pool.clone(), category_name, &parent_id)
+        .map_err(|e| format!("Could not add category: {}", e))?;
+
+    category_view(pool);
+    Ok(())
+}
+
+/// Give a category a new parent, refusing to create a cycle
+pub fn move_category(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    category: &str,
+    parent: Option<String>,
+) -> Result<(), String> {
+    let category_id = resolve_category(pool.clone(), category)?;
+    let parent_id = match parent {
+        Some(parent) => {
+            let parent_id = resolve_category(pool.clone(), &parent)?;
+            let descendants = sql::get_category_descendants(pool.clone(), &category_id).unwrap();
+            if descendants.contains(&parent_id) {
+                return Err(format!(
+                    "`{}` is `{}` or one of its subcategories",
+                    parent, category
+                ));
+            }
+            Some(parent_id)
+        }
+        None => None,
+    };
+
+    sql::set_category_parent(pool.clone(), &category_id, &parent_id)
+        .map_err(|e| format!("Could not move category: {}", e))?;
+
+    category_view(pool);
+    Ok(())
+}
+
+pub fn rename_category(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    category: &str,
+    category_name: &str,
+) -> Result<(), String> {
+    let category_id = resolve_category(pool.clone(), category)?;
+
+    sql::rename_category(pool.clone(), &category_id, category_name)
+        .map_err(|e| format!("Could not rename category: {}", e))?;
+
+    category_view(pool);
+    Ok(())
+}
+
+/// Move everything in one category into another and remove the first
+pub fn merge_category(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    from: &str,
+    into: &str,
+    skip_confirm: bool,
+) -> Result<(), String> {
+    let from_id = resolve_category(pool.clone(), from)?;
+    let to_id = resolve_category(pool.clone(), into)?;
+    let descendants = sql::get_category_descendants(pool.clone(), &from_id).unwrap();
+    if descendants.contains(&to_id) {
+        return Err(format!(
+            "Cannot merge `{}` into itself or one of its subcategories",
+            from
+        ));
+    }
+
+    let (expenses, subscriptions, children) = sql::category_usage(pool.clone(), &from_id).unwrap();
+    println!(
+        "{} expenses, {} subscriptions and {} subcategories will be moved from `{}` to `{}`",
+        expenses, subscriptions, children, from, into
+    );
+    if !skip_confirm {
+        require_prompt("yes")?;
+        if !user_input_confirm("Merge and delete the category?") {
+            return Ok(());
+        }
+    }
+
+    sql::merge_category(pool.clone(), &from_id, &to_id)
+        .map_err(|e| format!("Could not merge category: {}", e))?;
+
+    category_view(pool);
+    Ok(())
+}
+
+/// Delete a category. If expenses, subscriptions or subcategories still use
+/// it they must be reassigned to another category, otherwise the delete is
+/// refused.
+pub fn delete_category(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    category: &str,
+    reassign: Option<String>,
+    skip_confirm: bool,
+) -> Result<(), String> {
+    if let Some(into) = reassign {
+        return merge_category(pool, category, &into, skip_confirm);
+    }
+
+    let category_id = resolve_category(pool.clone(), category)?;
+    let (expenses, subscriptions, children) =
+        sql::category_usage(pool.clone(), &category_id).unwrap();
+    if expenses > 0 || subscriptions > 0 || children > 0 {
+        return Err(format!(
+            "`{}` is used by {} expenses, {} subscriptions and {} subcategories; use --reassign <category> to move them",
+            category, expenses, subscriptions, children
+        ));
+    }
+
+    if !skip_confirm {
+        require_prompt("yes")?;
+        if !user_input_confirm("Delete the category?") {
+            return Ok(());
+        }
+    }
+
+    sql::delete_category(pool.clone(), &category_id)
+        .map_err(|e| format!("Could not delete category: {}", e))?;
+
+    category_view(pool);
+    Ok(())
+}
+
+pub fn income_rows_to_table(rows: Vec<Row>) -> String {
+    let mut table = comfy_table::Table::new();
+    table
+        .load_preset(ASCII_MARKDOWN)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(vec!["id", "Date", "Account", "Amount", "Source", "Notes"]);
+
+    for row in rows {
+        let id: i32 = row.get(0);
+        let date: Option<NaiveDate> = row.get(1);
+        let account: Option<&str> = row.get(2);
+        let amount: Option<&str> = row.get(3);
+        let source: Option<&str> = row.get(4);
+        let notes: Option<&str> = row.get(5);
+
+        table.add_row(vec![
+            Cell::new(id),
+            Cell::new(date.map_or(String::new(), |date| date.to_string())),
+            Cell::new(account.unwrap_or("")),
+            Cell::new(amount.unwrap_or("")),
+            Cell::new(source.unwrap_or("")),
+            Cell::new(notes.unwrap_or("")),
+        ]);
+    }
+
+    table.to_string()
+}
+
+/// Add an income from the given values, prompting only for the ones left out
+pub fn add_income_prompt(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    date: Option<NaiveDate>,
+    account: Option<String>,
+    amount: Option<Decimal>,
+    source: Option<String>,
+    note: Option<String>,
+) -> Result<(), String> {
+    let date = match date {
+        Some(date) => date,
+        None => {
+            require_prompt("date")?;
+            user_input_date("Enter date")
+        }
+    };
+
+    let account_id = match account {
+        Some(account) => resolve_account(pool.clone(), &account)?,
+        None => {
+            require_prompt("account")?;
+            let table_vec: Vec<Row> = sql::get_account_ids(pool.clone()).unwrap();
+            println!("{}", account_rows_to_table(table_vec));
+
+            user_input_int("Enter ID")
+        }
+    };
+
+    let amount = match amount {
+        Some(amount) => amount,
+        None => {
+            require_prompt("amount")?;
+            let amount_input = user_input_float("Enter Amount");
+            Decimal::from_str(&amount_input.to_string()).unwrap()
+        }
+    };
+    check_amount(&amount)?;
+
+    let source = match source {
+        Some(source) => source,
+        None => {
+            require_prompt("source")?;
+            user_input_text("Source")
+        }
+    };
+
+    let note = match note {
+        Some(note) => note,
+        None if can_prompt() => user_input_text("Note"),
+        None => String::new(),
+    };
+
+    sql::add_income(pool.clone(), &date, &account_id, &amount, &source, note)
+        .map_err(|e| format!("Could not add income: {}", e))
+}
+
+/// Income, expenses, net and savings rate per period, with a total row
+pub fn cashflow_rows_to_table(rows: Vec<Row>, period: &Period) -> String {
+    let mut table = comfy_table::Table::new();
+    table
+        .load_preset(ASCII_MARKDOWN)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(vec!["Period", "Income", "Expenses", "Net", "Savings Rate"]);
+
+    let entries: Vec<(NaiveDate, Decimal, Decimal)> = rows
+        .iter()
+        .map(|row| (row.get(0), row.get(1), row.get(2)))
+        .collect();
+    let starts = match (entries.first(), entries.last()) {
+        (Some(first), Some(last)) => period.range(&first.0, &last.0),
+        _ => Vec::new(),
+    };
+
+    let mut total_income = Decimal::zero();
+    let mut total_expenses = Decimal::zero();
+    let mut add_row = |label: String, income: Decimal, expenses: Decimal| {
+        table.add_row(vec![
+            Cell::new(label),
+            Cell::new(money(&income)),
+            Cell::new(money(&expenses)),
+            Cell::new(money(&(income - expenses))),
+            Cell::new(savings_rate(&income, &expenses)),
+        ]);
+    };
+
+    for start in starts {
+        let (income, expenses) = match entries.iter().find(|e| e.0 == start) {
+            Some(entry) => (entry.1, entry.2),
+            None => (Decimal::zero(), Decimal::zero()),
+        };
+        total_income += income;
+        total_expenses += expenses;
+        add_row(period.label(&start), income, expenses);
+    }
+    add_row("Total".to_string(), total_income, total_expenses);
+
+    table.to_string()
+}
+
+/// Share of income that was not spent, as a percentage
+fn savings_rate(income: &Decimal, expenses: &Decimal) -> String {
+    if *income > Decimal::zero() {
+        format!(
+            "{:.1}%",
+            ((*income - *expenses) / *income * Decimal::from(100)).round_dp(1)
+        )
+    } else {
+        "".to_string()
+    }
+}
+
+pub fn transfer_rows_to_table(rows: Vec<Row>) -> String {
+    let mut table = comfy_table::Table::new();
+    table
+        .load_preset(ASCII_MARKDOWN)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(vec!["id", "Date", "From", "To", "Amount", "Notes"]);
+
+    for row in rows {
+        let id: i32 = row.get(0);
+        let date: Option<NaiveDate> = row.get(1);
+        let from: Option<&str> = row.get(2);
+        let to: Option<&str> = row.get(3);
+        let amount: Option<&str> = row.get(4);
+        let notes: Option<&str> = row.get(5);
+
+        table.add_row(vec![
+            Cell::new(id),
+            Cell::new(date.map_or(String::new(), |date| date.to_string())),
+            Cell::new(from.unwrap_or("")),
+            Cell::new(to.unwrap_or("")),
+            Cell::new(amount.unwrap_or("")),
+            Cell::new(notes.unwrap_or("")),
+        ]);
+    }
+
+    table.to_string()
+}
+
+/// Move money between two accounts, prompting for any value left out
+pub fn transfer_prompt(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    date: Option<NaiveDate>,
+    from: Option<String>,
+    to: Option<String>,
+    amount: Option<Decimal>,
+    note: Option<String>,
+) -> Result<(), String> {
+    let date = match date {
+        Some(date) => date,
+        None => {
+            require_prompt("date")?;
+            user_input_date("Enter date")
+        }
+    };
+
+    if from.is_none() || to.is_none() {
+        require_prompt(if from.is_none() { "from" } else { "to" })?;
+        let table_vec: Vec<Row> = sql::get_account_values(pool.clone()).unwrap();
+        println!("{}", account_values_to_table(&table_vec));
+    }
+    let from_id = match from {
+        Some(from) => resolve_account(pool.clone(), &from)?,
+        None => user_input_int("ID of Account to Transfer From"),
+    };
+    let to_id = match to {
+        Some(to) => resolve_account(pool.clone(), &to)?,
+        None => user_input_int("ID of Account to Transfer To"),
+    };
+    if from_id == to_id {
+        return Err("Cannot transfer from an account to itself".to_string());
+    }
+
+    let amount = match amount {
+        Some(amount) => amount,
+        None => {
+            require_prompt("amount")?;
+            let amount_input = user_input_float("Enter Amount");
+            Decimal::from_str(&amount_input.to_string()).unwrap()
+        }
+    };
+    check_amount(&amount)?;
+
+    let note = match note {
+        Some(note) => note,
+        None if can_prompt() => user_input_text("Note"),
+        None => String::new(),
+    };
+
+    sql::add_transfer(pool.clone(), &date, &from_id, &to_id, &amount, note)
+        .map_err(|e| format!("Could not record transfer: {}", e))?;
+
+    let table_vec: Vec<Row> = sql::get_account_values(pool.clone()).unwrap();
+    println!("{}", account_values_to_table(&table_vec));
+
+    Ok(())
+}
+
+/// Show transfer history, optionally for a single account
+pub fn transfer_history(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    account: Option<String>,
+    n: Option<i64>,
+) -> Result<(), String> {
+    let account_id = match account {
+        Some(account) => Some(resolve_account(pool.clone(), &account)?),
+        None => None,
+    };
+
+    let table_vec: Vec<Row> = sql::get_transfers(pool.clone(), &account_id, &n).unwrap();
+    println!("{}", transfer_rows_to_table(table_vec));
+
+    Ok(())
+}
+
+/// Turn on (or off) keeping an account's value in step with the expenses,
+/// income and transfers recorded against it
+pub fn track_account(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    account: &str,
+    track: bool,
+    opening_balance: Option<Decimal>,
+    opening_date: Option<NaiveDate>,
+) -> Result<(), String> {
+    let account_id = resolve_account(pool.clone(), account)?;
+    let explicit_opening = opening_balance.is_some() || opening_date.is_some();
+
+    sql::set_account_tracking(
+        pool.clone(),
+        &account_id,
+        track,
+        &opening_balance,
+        &opening_date,
+    )
+    .map_err(|e| format!("Could not update account: {}", e))?;
+
+    if track && explicit_opening {
+        recompute_accounts(pool.clone(), Some(account.to_string()))?;
+    } else {
+        tracked_accounts_view(pool);
+    }
+
+    Ok(())
+}
+
+fn tracked_accounts_view(pool: r2d2::Pool<PostgresConnectionManager<NoTls>>) {
+    let mut table = comfy_table::Table::new();
+    table
+        .load_preset(ASCII_MARKDOWN)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(vec![
+            "id",
+            "Tracked Account",
+            "Opening Balance",
+            "Opening Date",
+        ]);
+
+    for row in sql::get_tracked_accounts(pool).unwrap() {
+        let id: i32 = row.get(0);
+        let account: &str = row.get(1);
+        let opening_balance: Decimal = row.get(2);
+        let opening_date: Option<NaiveDate> = row.get(3);
+
+        table.add_row(vec![
+            Cell::new(id),
+            Cell::new(account),
+            Cell::new(opening_balance),
+            Cell::new(opening_date.map_or("".to_string(), |d| d.to_string())),
+        ]);
+    }
+
+    println!("{}", table);
+}
+
+/// Rebuild the value of one tracked account, or all of them, from the
+/// opening balance and recorded transactions
+pub fn recompute_accounts(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    account: Option<String>,
+) -> Result<(), String> {
+    let tracked: Vec<i32> = sql::get_tracked_accounts(pool.clone())
+        .unwrap()
+        .iter()
+        .map(|row| row.get(0))
+        .collect();
+
+    let account_ids = match account {
+        Some(account) => {
+            let account_id = resolve_account(pool.clone(), &account)?;
+            if !tracked.contains(&account_id) {
+                return Err(format!(
+                    "`{}` does not track its balance; turn it on with `account track {}`",
+                    account, account
+                ));
+            }
+            vec![account_id]
+        }
+        None => tracked,
+    };
+
+    for account_id in account_ids {
+        sql::recompute_account_value(pool.clone(), &account_id)
+            .map_err(|e| format!("Could not recompute account: {}", e))?;
+    }
+
+    let table_vec: Vec<Row> = sql::get_account_values(pool.clone()).unwrap();
+    println!("{}", account_values_to_table(&table_vec));
+
+    Ok(())
+}
+
+/// Show every recorded value of an account and how much it changed
+pub fn account_history(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    account: &str,
+) -> Result<(), String> {
+    let account_id = resolve_account(pool.clone(), account)?;
+
+    let mut table = comfy_table::Table::new();
+    table
+        .load_preset(ASCII_MARKDOWN)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(vec!["Recorded", "Value", "Change"]);
+
+    for row in sql::get_account_history(pool, &account_id).unwrap() {
+        let recorded_at: Option<NaiveDateTime> = row.get(0);
+        let value: Decimal = row.get(1);
+        let change: Option<Decimal> = row.get(2);
+
+        table.add_row(vec![
+            Cell::new(
+                recorded_at.map_or("before history".to_string(), |recorded_at| {
+                    recorded_at.format("%Y-%m-%d %H:%M").to_string()
+                }),
+            ),
+            Cell::new(value),
+            Cell::new(change.map_or("".to_string(), |c| format!("{:+}", c))),
+        ]);
+    }
+
+    println!("{}", table);
+    Ok(())
+}
+
+/// Account values grouped by account type, split into assets and liabilities
+struct NetWorthSummary {
+    /// Account type, whether it is a liability, total value
+    types: Vec<(String, bool, Decimal)>,
+    assets: Decimal,
+    liabilities: Decimal,
+}
+
+/// Group (account type, value) pairs and add the portfolio as an asset.
+/// Spending lowers a balance, so liability types are owed the negated
+/// balance, and an overpaid one adds to net worth.
+fn net_worth(
+    values: &[(String, Decimal)],
+    portfolio: Decimal,
+    liability_types: &[String],
+) -> NetWorthSummary {
+    let mut types: Vec<(String, bool, Decimal)> = Vec::new();
+    for (account_type, value) in values {
+        let liability = liability_types
+            .iter()
+            .any(|t| t.to_lowercase() == account_type.to_lowercase());
+        let value = if liability { -*value } else { *value };
+        match types.iter_mut().find(|t| &t.0 == account_type) {
+            Some(t) => t.2 += value,
+            None => types.push((account_type.to_string(), liability, value)),
+        }
+    }
+    types.sort_by(|a, b| (a.1, &a.0).cmp(&(b.1, &b.0)));
+
+    let assets = types.iter().filter(|t| !t.1).map(|t| t.2).sum::<Decimal>() + portfolio;
+    let liabilities = types.iter().filter(|t| t.1).map(|t| t.2).sum();
+
+    NetWorthSummary {
+        types,
+        assets,
+        liabilities,
+    }
+}
+
+fn account_type_values(rows: &[Row]) -> Vec<(String, Decimal)> {
+    rows.iter().map(|row| (row.get(3), row.get(2))).collect()
+}
+
+/// Show the latest account values by type, the portfolio, and net worth
+pub fn net_worth_view(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    liability_types: &[String],
+) {
+    let accounts: Vec<Row> = sql::get_account_values(pool.clone()).unwrap();
+    let portfolio = match sql::get_portfolio_date(pool.clone(), &None).unwrap() {
+        Some(date) => sql::get_portfolio_total_on(pool.clone(), &date).unwrap(),
+        None => Decimal::zero(),
+    };
+
+    let summary = net_worth(&account_type_values(&accounts), portfolio, liability_types);
+
+    let mut table = comfy_table::Table::new();
+    table
+        .load_preset(ASCII_MARKDOWN)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(vec!["", "Type", "Value"]);
+
+    for (account_type, liability, value) in &summary.types {
+        let kind = if *liability { "Liability" } else { "Asset" };
+        table.add_row(vec![
+            Cell::new(kind),
+            Cell::new(account_type),
+            Cell::new(money(value)),
+        ]);
+    }
+    table.add_row(vec![
+        Cell::new("Asset"),
+        Cell::new("Portfolio"),
+        Cell::new(money(&portfolio)),
+    ]);
+    table.add_row(vec![
+        Cell::new("Total"),
+        Cell::new("Assets"),
+        Cell::new(money(&summary.assets)),
+    ]);
+    table.add_row(vec![
+        Cell::new("Total"),
+        Cell::new("Liabilities"),
+        Cell::new(money(&summary.liabilities)),
+    ]);
+    table.add_row(vec![
+        Cell::new("Total"),
+        Cell::new("Net Worth"),
+        Cell::new(money(&(summary.assets - summary.liabilities))),
+    ]);
+
+    println!("{}", table);
+}
+
+/// Show assets, liabilities and net worth at the end of each of the last
+/// `months` months
+pub fn net_worth_series(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    liability_types: &[String],
+    months: u32,
+) {
+    let mut table = comfy_table::Table::new();
+    table
+        .load_preset(ASCII_MARKDOWN)
+        .set_content_arrangement(ContentArrangement::Dynamic)
+        .set_header(vec![
+            "Month",
+            "Assets",
+            "Liabilities",
+            "Net Worth",
+            "Change",
+        ]);
+
+    let this_month = Period::Month.start_of(&Local::today().naive_local());
+    let mut start = this_month;
+    for _ in 1..months {
+        start = Period::Month.previous(&start);
+    }
+
+    let mut previous: Option<Decimal> = None;
+    while start <= this_month {
+        let end = Period::Month.next(&start).pred();
+        let accounts: Vec<Row> = sql::get_account_values_on(pool.clone(), &Some(end)).unwrap();
+        let portfolio = sql::get_portfolio_total_on(pool.clone(), &end).unwrap();
+        let summary = net_worth(&account_type_values(&accounts), portfolio, liability_types);
+        let net = summary.assets - summary.liabilities;
+
+        table.add_row(vec![
+            Cell::new(Period::Month.label(&start)),
+            Cell::new(money(&summary.assets)),
+            Cell::new(money(&summary.liabilities)),
+            Cell::new(money(&net)),
+            Cell::new(previous.map_or("".to_string(), |p| money(&(net - p)))),
+        ]);
+
+        previous = Some(net);
+        start = Period::Month.next(&start);
+    }
+
+    println!("{}", table);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_categories() -> Vec<(i32, String, Option<i32>)> {
+        vec![
+            (1, "Food".to_string(), None),
+            (2, "Rent".to_string(), None),
+            (3, "Restaurants".to_string(), Some(1)),
+            (4, "Groceries".to_string(), Some(1)),
+            (5, "Organic".to_string(), Some(4)),
+        ]
+    }
+
+    #[test]
+    fn test_category_tree() {
+        let tree = category_tree(test_categories());
+        let names: Vec<String> = tree.iter().map(|node| node.indented_name()).collect();
+
+        assert_eq!(
+            vec![
+                "Food",
+                "  Groceries",
+                "    Organic",
+                "  Restaurants",
+                "Rent"
+            ],
+            names
+        );
+    }
+
+    #[test]
+    fn test_pivot_fills_missing_periods() {
+        let tree = category_tree(test_categories());
+        let entries = vec![
+            (
+                Some(1),
+                NaiveDate::from_ymd(2021, 1, 1),
+                Decimal::new(1050, 2),
+            ),
+            (
+                Some(2),
+                NaiveDate::from_ymd(2021, 1, 1),
+                Decimal::new(500, 0),
+            ),
+            (
+                Some(1),
+                NaiveDate::from_ymd(2021, 3, 1),
+                Decimal::new(20, 0),
+            ),
+        ];
+        let pivot = pivot(&entries, &tree, &Period::Month);
+
+        assert_eq!(3, pivot.periods.len());
+        assert_eq!(
+            vec![Decimal::new(1050, 2), Decimal::zero(), Decimal::new(20, 0)],
+            pivot.rows[0].1
+        );
+        assert_eq!("Rent", pivot.rows[1].0);
+        assert_eq!(Decimal::zero(), pivot.rows[1].1[2]);
+    }
+
+    #[test]
+    fn test_pivot_rolls_up_children() {
+        let tree = category_tree(test_categories());
+        let month = NaiveDate::from_ymd(2021, 1, 1);
+        let entries = vec![
+            (Some(5), month, Decimal::new(10, 0)),
+            (Some(3), month, Decimal::new(25, 0)),
+            (None, month, Decimal::new(5, 0)),
+        ];
+        let pivot = pivot(&entries, &tree, &Period::Month);
+        let rows: Vec<(&str, Decimal)> = pivot
+            .rows
+            .iter()
+            .map(|(name, values)| (name.as_str(), values[0]))
+            .collect();
+
+        assert_eq!(
+            vec![
+                ("Food", Decimal::new(35, 0)),
+                ("  Groceries", Decimal::new(10, 0)),
+                ("    Organic", Decimal::new(10, 0)),
+                ("  Restaurants", Decimal::new(25, 0)),
+                ("Uncategorized", Decimal::new(5, 0)),
+            ],
+            rows
+        );
+        assert_eq!(vec![Decimal::new(40, 0)], pivot.totals);
+    }
+
+    #[test]
+    fn test_periods_between() {
+        let d = |m, day| NaiveDate::from_ymd(2021, m, day);
+        assert_eq!(0, periods_between(&Period::Month, &d(3, 14), &d(3, 1)));
+        assert_eq!(2, periods_between(&Period::Month, &d(3, 14), &d(5, 1)));
+        assert_eq!(0, periods_between(&Period::Month, &d(6, 2), &d(5, 1)));
+        assert_eq!(1, periods_between(&Period::Week, &d(3, 14), &d(3, 15)));
+    }
+
+    #[test]
+    fn test_active_ranges() {
+        use SubscriptionStatus::*;
+        let d = |m, day| NaiveDate::from_ymd(2021, m, day);
+        let mut subscription = Subscription {
+            id: 1,
+            name: "Netflix".to_string(),
+            category: "Entertainment".to_string(),
+            price: Decimal::new(1549, 2),
+            interval: BillingInterval::Month,
+            start: Some(d(1, 15)),
+            account_id: Some(1),
+            category_id: 1,
+            status: Active,
+            status_date: None,
+            last_change: None,
+        };
+        let today = d(6, 1);
+
+        assert_eq!(
+            vec![(d(1, 15), today)],
+            subscription.active_ranges(&[], &today)
+        );
+        assert_eq!(
+            vec![(d(1, 15), d(3, 14))],
+            subscription.active_ranges(&[(d(3, 15), Paused)], &today)
+        );
+        // renewals before a pause are still due after resuming
+        assert_eq!(
+            vec![(d(1, 15), d(3, 14)), (d(5, 1), today)],
+            subscription.active_ranges(&[(d(3, 15), Paused), (d(5, 1), Active)], &today)
+        );
+        // history that starts with a resume
+        assert_eq!(
+            vec![(d(5, 1), today)],
+            subscription.active_ranges(&[(d(5, 1), Active)], &today)
+        );
+        assert_eq!(
+            vec![(d(1, 15), d(2, 28))],
+            subscription.active_ranges(&[(d(3, 1), Paused), (d(4, 1), Cancelled)], &today)
+        );
+
+        subscription.start = None;
+        assert!(subscription.active_ranges(&[], &today).is_empty());
+    }
+
+    #[test]
+    fn test_status_transition() {
+        use SubscriptionStatus::*;
+        assert!(Active.check_transition(&Paused).is_ok());
+        assert!(Paused.check_transition(&Cancelled).is_ok());
+        assert!(Cancelled.check_transition(&Active).is_ok());
+        assert_eq!(
+            Err("is already active".to_string()),
+            Active.check_transition(&Active)
+        );
+        assert_eq!(
+            Err("is cancelled, resume it before it can be paused".to_string()),
+            Cancelled.check_transition(&Paused)
+        );
+        assert_eq!(Ok(Cancelled), "Cancelled".parse());
+    }
+
+    #[test]
+    fn test_price_on() {
+        let d = |m, day| NaiveDate::from_ymd(2021, m, day);
+        let current = Decimal::new(1799, 2);
+        let changes = vec![
+            (d(3, 1), Decimal::new(1399, 2)),
+            (d(6, 1), Decimal::new(1549, 2)),
+        ];
+
+        assert_eq!(
+            Decimal::new(1399, 2),
+            price_on(&current, &changes, &d(2, 28))
+        );
+        assert_eq!(
+            Decimal::new(1549, 2),
+            price_on(&current, &changes, &d(3, 1))
+        );
+        assert_eq!(
+            Decimal::new(1549, 2),
+            price_on(&current, &changes, &d(5, 31))
+        );
+        assert_eq!(current, price_on(&current, &changes, &d(6, 1)));
+        assert_eq!(current, price_on(&current, &[], &d(1, 1)));
+        assert_eq!(
+            Decimal::new(10, 0),
+            convert_price(
+                &Decimal::new(120, 0),
+                &BillingInterval::Year,
+                &BillingInterval::Month
+            )
+        );
+    }
+
+    #[test]
+    fn test_percent_change() {
+        assert_eq!(
+            Some(Decimal::new(25, 0)),
+            percent_change(&Decimal::new(80, 0), &Decimal::new(100, 0))
+        );
+        assert_eq!(
+            Some(Decimal::new(-100, 0)),
+            percent_change(&Decimal::new(80, 0), &Decimal::zero())
+        );
+        assert_eq!(
+            None,
+            percent_change(&Decimal::zero(), &Decimal::new(100, 0))
+        );
+    }
+
+    #[test]
+    fn test_rebalance() {
+        let values = vec![
+            ("Stocks".to_string(), Decimal::new(700, 0)),
+            ("Bonds".to_string(), Decimal::new(200, 0)),
+            ("Unassigned".to_string(), Decimal::new(100, 0)),
+        ];
+        let targets = vec![
+            ("Bonds".to_string(), Some(Decimal::new(40, 0))),
+            ("Cash".to_string(), None),
+            ("Gold".to_string(), Some(Decimal::new(10, 0))),
+            ("Stocks".to_string(), Some(Decimal::new(50, 0))),
+        ];
+        let allocations = rebalance(&values, &targets, &Decimal::new(1000, 0));
+
+        // Cash has neither a target nor holdings, so it is left out
+        assert_eq!(4, allocations.len());
+        assert_eq!(
+            Allocation {
+                class: "Bonds".to_string(),
+                value: Decimal::new(200, 0),
+                current: Some(Decimal::new(200, 0) / Decimal::new(900, 0) * Decimal::from(100)),
+                target: Some(Decimal::new(40, 0)),
+                trade: Some(Decimal::new(560, 0)),
+            },
+            allocations[0]
+        );
+        assert_eq!(Some(Decimal::new(190, 0)), allocations[1].trade);
+        assert_eq!(Some(Decimal::new(250, 0)), allocations[2].trade);
+        assert_eq!("Unassigned", allocations[3].class);
+        assert_eq!(None, allocations[3].current);
+        assert_eq!(None, allocations[3].trade);
+        assert_eq!(
+            Decimal::new(1000, 0),
+            allocations.iter().filter_map(|a| a.trade).sum::<Decimal>()
+        );
+    }
+
+    #[test]
+    fn test_net_worth() {
+        let values = vec![
+            ("Checking".to_string(), Decimal::new(1000, 0)),
+            ("Credit Card".to_string(), Decimal::new(-200, 0)),
+            ("Savings".to_string(), Decimal::new(5000, 0)),
+            ("credit card".to_string(), Decimal::new(50, 0)),
+            ("Checking".to_string(), Decimal::new(500, 0)),
+        ];
+        let liability_types = vec!["Credit Card".to_string()];
+        let summary = net_worth(&values, Decimal::new(10000, 0), &liability_types);
+
+        assert_eq!(Decimal::new(16500, 0), summary.assets);
+        // the overpaid card is owed back, so it lowers the liabilities
+        assert_eq!(Decimal::new(150, 0), summary.liabilities);
+        assert_eq!(
+            ("Checking".to_string(), false, Decimal::new(1500, 0)),
+            summary.types[0]
+        );
+        assert!(summary.types[2].1);
+    }
+
+    #[test]
+    fn test_savings_rate() {
+        assert_eq!(
+            "25.0%",
+            savings_rate(&Decimal::new(4000, 0), &Decimal::new(3000, 0))
+        );
+        assert_eq!(
+            "-12.5%",
+            savings_rate(&Decimal::new(800, 0), &Decimal::new(900, 0))
+        );
+        assert_eq!("", savings_rate(&Decimal::zero(), &Decimal::new(10, 0)));
+    }
 }
@@ -1,71 +1,35 @@
 extern crate directories;
+use chrono::Local;
 use directories::ProjectDirs;
 use postgres::Row;
-use r2d2;
 use r2d2_postgres::{postgres::NoTls, PostgresConnectionManager};
 use serde::{Deserialize, Serialize};
 use std::env;
 use std::fs::{create_dir_all, read_to_string, File};
 use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::process;
 use structopt::StructOpt;
-use toml;
 
+mod cli;
 mod csv;
 mod datetime;
+mod ical;
 mod interface;
 mod sql;
 
-#[derive(StructOpt)]
-pub struct Opts {
-    /// expense, subscription, portfolio, account
-    main: String,
-
-    // SUBCOMMAND
-    #[structopt(subcommand)]
-    subcommand: Option<Sub>,
-}
-
-#[derive(StructOpt)]
-enum Sub {
-    /// View items
-    #[structopt(name = "view")]
-    View(ViewOpts),
-
-    /// Add new
-    #[structopt(name = "add")]
-    Add(AddOpts),
-
-    /// Configure
-    #[structopt(name = "manage")]
-    Manage(ManageOpts),
-}
-
-#[derive(StructOpt, Debug)]
-struct ViewOpts {
-    /// Specify Number of Items to Display
-    #[structopt(short)]
-    number: Option<String>,
-
-    /// Specify Category
-    #[structopt(short)]
-    category: Option<String>,
-}
-
-#[derive(StructOpt, Debug)]
-struct AddOpts {
-    /// Read CSV
-    #[structopt(short)]
-    file: Option<String>,
-}
-
-#[derive(StructOpt, Debug)]
-struct ManageOpts {}
+use cli::{
+    AccountCmd, BudgetCmd, CategoryCmd, ExpenseCmd, ExpenseFilterOpts, IncomeCmd, Opts,
+    PortfolioCmd, SubscriptionCmd, SubscriptionViewOpts,
+};
+use interface::SubscriptionStatus;
 
 #[derive(Serialize, Deserialize)]
 struct Config {
     database: Database,
-    csv: Csv,
+    csv: csv::CsvConfig,
+    #[serde(default)]
+    networth: NetWorth,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -78,12 +42,17 @@ struct Database {
 }
 
 #[derive(Serialize, Deserialize)]
-struct Csv {
-    currency: String,
-    skiprows: usize,
-    stoprows: usize,
-    item_column: usize,
-    value_column: usize,
+struct NetWorth {
+    /// Account types whose balances are owed rather than owned
+    liability_types: Vec<String>,
+}
+
+impl Default for NetWorth {
+    fn default() -> Self {
+        NetWorth {
+            liability_types: vec!["Credit Card".to_string(), "Loan".to_string()],
+        }
+    }
 }
 
 fn main() {
@@ -95,13 +64,9 @@ fn main() {
         dbpassword: "".to_string(),
     };
 
-    let mut csv: Csv = Csv {
-        currency: "".to_string(),
-        skiprows: 0,
-        stoprows: 0,
-        item_column: 1,
-        value_column: 2,
-    };
+    let mut csv = csv::CsvConfig::default();
+
+    let mut networth = NetWorth::default();
 
     if let Some(proj_dirs) = ProjectDirs::from("haibun", "haibun", "haibun") {
         let path = proj_dirs.config_dir();
@@ -109,13 +74,16 @@ fn main() {
 
         // If configuration file does not exist
         if !Path::new(&config_path).exists() {
-            create_config(&path, &config_path);
+            create_config(path, &config_path);
             // Quit
         }
         // Read postgres config from file if exist
         else {
             let contents = read_to_string(&config_path).expect("Error Reading Config");
-            let config: Config = toml::from_str(&contents).unwrap();
+            let config: Config = toml::from_str(&contents).unwrap_or_else(|e| {
+                eprintln!("Invalid config {}: {}", config_path.display(), e);
+                process::exit(1);
+            });
 
             login = Database {
                 ip: config.database.ip,
@@ -125,13 +93,9 @@ fn main() {
                 dbpassword: config.database.dbpassword,
             };
 
-            csv = Csv {
-                currency: config.csv.currency,
-                skiprows: config.csv.skiprows,
-                stoprows: config.csv.stoprows,
-                item_column: config.csv.item_column,
-                value_column: config.csv.value_column,
-            };
+            csv = config.csv;
+
+            networth = config.networth;
         }
     }
 
@@ -143,126 +107,370 @@ fn main() {
     let manager = PostgresConnectionManager::new(c.parse().unwrap(), NoTls);
     let pool = r2d2::Pool::new(manager).unwrap();
 
-    // Command line arguments
-    let args = Opts::from_args();
-
-    if args.main == "expense" {
-        if let Some(subcommand) = args.subcommand {
-            match subcommand {
-                Sub::View(opt) => {
-                    if opt.category.is_none() {
-                        let number = opt
-                            .number
-                            .as_deref()
-                            .unwrap_or("10")
-                            .parse::<i64>()
-                            .unwrap();
-                        let table_vec: Vec<Row> =
-                            sql::get_expense_num(pool.clone(), &number).unwrap();
-
-                        let table_string = interface::expense_rows_to_table(table_vec);
-                        println!("{}", table_string);
-                    } else {
-                        let category = &opt.category.as_deref().unwrap();
-                        let n = sql::expense_category_count(pool.clone(), &category).unwrap();
-                        let number = opt
-                            .number
-                            .as_deref()
-                            .unwrap_or(&n.to_string())
-                            .parse::<i64>()
-                            .unwrap();
-
-                        let table_vec: Vec<Row> =
-                            sql::get_expense_category(pool.clone(), &number, &category).unwrap();
-
-                        let table_string = interface::expense_rows_to_table(table_vec);
-                        println!("{}", table_string);
-                    }
-                }
-                Sub::Add(_opt) => {
-                    interface::add_expense_prompt(pool.clone());
-                }
-                Sub::Manage(_opt) => {
-                    unimplemented!();
-                }
+    match Opts::from_args() {
+        Opts::Expense(cmd) => expense(pool, cmd),
+        Opts::Subscription { cmd } => subscription(
+            pool,
+            cmd.unwrap_or(SubscriptionCmd::View(SubscriptionViewOpts { all: false })),
+        ),
+        Opts::Account(cmd) => account(pool, cmd),
+        Opts::Portfolio(cmd) => portfolio(pool, cmd, csv),
+        Opts::Budget(cmd) => budget(pool, cmd),
+        Opts::Category(cmd) => category(pool, cmd),
+        Opts::Income(cmd) => income(pool, cmd),
+        Opts::Cashflow(opt) => {
+            let (from, to) = opt.range.bounds();
+            let table_vec: Vec<Row> = sql::get_cashflow(pool, &opt.by, &from, &to).unwrap();
+            let table_string = interface::cashflow_rows_to_table(table_vec, &opt.by);
+            println!("{}", table_string);
+        }
+        Opts::Networth(opt) => match opt.months {
+            Some(months) => {
+                interface::net_worth_series(pool, &networth.liability_types, months);
             }
-        } else {
-            unimplemented!();
-        }
-    } else if args.main == "subscription" {
-        if let Some(subcommand) = args.subcommand {
-            match subcommand {
-                Sub::View(_opt) => {
-                    let mut table_vec: Vec<Row> = sql::get_subscriptions(pool.clone()).unwrap();
-                    let table_vec_sum: Row = sql::get_subscriptions_sum(pool.clone()).unwrap();
-                    table_vec.push(table_vec_sum);
-                    let table_string = interface::subscription_rows_to_table(table_vec);
-                    println!("{}", table_string);
-                }
-                Sub::Add(_opt) => {
-                    interface::add_subscription_prompt(pool.clone());
-                }
-                Sub::Manage(_opt) => {
-                    unimplemented!();
-                }
+            None => interface::net_worth_view(pool, &networth.liability_types),
+        },
+    }
+}
+
+fn expense(pool: r2d2::Pool<PostgresConnectionManager<NoTls>>, cmd: ExpenseCmd) {
+    match cmd {
+        ExpenseCmd::View(opt) => {
+            let mut filter = expense_filter(opt.filter);
+            filter.limit = opt.number;
+            if filter.limit.is_none() && filter.is_unfiltered() {
+                filter.limit = Some(10);
             }
-        } else {
-            let table_vec: Vec<Row> = sql::get_subscriptions(pool.clone()).unwrap();
-            let table_string = interface::subscription_rows_to_table(table_vec);
+
+            let table_vec: Vec<Row> = sql::get_expenses(pool.clone(), &filter).unwrap();
+
+            let table_string = interface::expense_rows_to_table(table_vec);
             println!("{}", table_string);
         }
-    } else if args.main == "account" {
-        if let Some(subcommand) = args.subcommand {
-            match subcommand {
-                Sub::View(_opt) => {
-                    let table_vec: Vec<Row> = sql::get_account_values(pool.clone()).unwrap();
-                    let table_string = interface::account_values_to_table(&table_vec);
-                    println!("{}", table_string);
-                }
-                Sub::Add(_opt) => {
-                    interface::add_account_prompt(pool.clone());
-                }
-                Sub::Manage(_opt) => {
-                    interface::update_account_values(pool.clone());
-                }
-            }
-        } else {
-            unimplemented!();
-        }
-    } else if args.main == "portfolio" {
-        if let Some(subcommand) = args.subcommand {
-            match subcommand {
-                Sub::View(_opt) => {
-                    let mut table_vec: Vec<Row> = sql::get_portfolio(pool.clone()).unwrap();
-                    let table_vec_sum: Row = sql::get_portfolio_sum(pool.clone()).unwrap();
-                    table_vec.push(table_vec_sum);
-                    let table_string = interface::portfolio_rows_to_table(table_vec);
-                    println!("{}", table_string);
-                    println!("{}", sql::portfolio_count(pool.clone()).unwrap());
-                }
-                Sub::Add(opt) => {
-                    let dir = env::current_dir().unwrap();
-                    let path = dir.join(opt.file.unwrap().replace(".\\", ""));
-                    csv::read_csv(
-                        pool.clone(),
-                        path.to_str().unwrap(),
-                        csv.currency,
-                        csv.skiprows,
-                        csv.stoprows,
-                        csv.item_column,
-                        csv.value_column,
-                    )
-                    .expect("Could not add from csv");
-                }
-                Sub::Manage(_opt) => {
-                    unimplemented!();
-                }
+        ExpenseCmd::Add(opt) => {
+            exit_on_error(interface::add_expense_prompt(
+                pool.clone(),
+                opt.date,
+                opt.account,
+                opt.amount,
+                opt.category,
+                opt.note,
+            ));
+        }
+        ExpenseCmd::Edit(opt) => {
+            exit_on_error(interface::edit_expense(
+                pool.clone(),
+                opt.id,
+                opt.fields.date,
+                opt.fields.account,
+                opt.fields.amount,
+                opt.fields.category,
+                opt.fields.note,
+            ));
+        }
+        ExpenseCmd::Delete(opt) => {
+            exit_on_error(interface::delete_expense(pool.clone(), opt.id, opt.yes));
+        }
+        ExpenseCmd::Report(opt) => {
+            let filter = expense_filter(opt.filter);
+            let table_vec: Vec<Row> =
+                sql::get_expense_totals(pool.clone(), &filter, &opt.by).unwrap();
+            let categories: Vec<Row> = sql::get_expense_categories(pool.clone()).unwrap();
+            let table_string = interface::expense_report_to_table(table_vec, categories, &opt.by);
+            println!("{}", table_string);
+        }
+    }
+}
+
+fn expense_filter(opt: ExpenseFilterOpts) -> sql::ExpenseFilter {
+    let (from, to) = opt.range.bounds();
+
+    sql::ExpenseFilter {
+        from,
+        to,
+        account: opt.account,
+        category: opt.category,
+        min: opt.min,
+        max: opt.max,
+        search: opt.search,
+        limit: None,
+    }
+}
+
+fn income(pool: r2d2::Pool<PostgresConnectionManager<NoTls>>, cmd: IncomeCmd) {
+    match cmd {
+        IncomeCmd::View(opt) => {
+            let (from, to) = opt.range.bounds();
+            let mut filter = sql::IncomeFilter {
+                from,
+                to,
+                account: opt.account,
+                source: opt.source,
+                limit: opt.number,
+            };
+            if filter.limit.is_none() && filter.is_unfiltered() {
+                filter.limit = Some(10);
             }
-        } else {
-            unimplemented!();
+
+            let table_vec: Vec<Row> = sql::get_incomes(pool.clone(), &filter).unwrap();
+            let table_string = interface::income_rows_to_table(table_vec);
+            println!("{}", table_string);
+        }
+        IncomeCmd::Add(opt) => {
+            exit_on_error(interface::add_income_prompt(
+                pool.clone(),
+                opt.date,
+                opt.account,
+                opt.amount,
+                opt.source,
+                opt.note,
+            ));
         }
-    } else {
-        println!("Unknown Command `{}`", args.main);
+    }
+}
+
+fn subscription(pool: r2d2::Pool<PostgresConnectionManager<NoTls>>, cmd: SubscriptionCmd) {
+    match cmd {
+        SubscriptionCmd::View(opt) => {
+            let table_vec: Vec<Row> = sql::get_subscriptions(pool.clone(), opt.all).unwrap();
+            let today = Local::today().naive_local();
+            let table_string = interface::subscription_rows_to_table(table_vec, &today);
+            println!("{}", table_string);
+        }
+        SubscriptionCmd::Add(opt) => {
+            exit_on_error(interface::add_subscription_prompt(
+                pool.clone(),
+                opt.name,
+                opt.category,
+                opt.price,
+                opt.every,
+                opt.start,
+                opt.account,
+            ));
+        }
+        SubscriptionCmd::Upcoming(opt) => {
+            interface::upcoming_subscriptions(pool.clone(), opt.days);
+        }
+        SubscriptionCmd::Ical(opt) => {
+            exit_on_error(interface::export_subscriptions_ical(
+                pool.clone(),
+                opt.output,
+            ));
+        }
+        SubscriptionCmd::Post(opt) => {
+            exit_on_error(interface::post_subscriptions(pool.clone(), opt.since));
+        }
+        SubscriptionCmd::Edit(opt) => {
+            exit_on_error(interface::edit_subscription(
+                pool.clone(),
+                &opt.subscription,
+                opt.fields.name,
+                opt.fields.category,
+                opt.fields.price,
+                opt.fields.every,
+                opt.fields.start,
+                opt.fields.account,
+            ));
+        }
+        SubscriptionCmd::Pause(opt) => {
+            exit_on_error(interface::set_subscription_status(
+                pool.clone(),
+                &opt.subscription,
+                SubscriptionStatus::Paused,
+                opt.date,
+            ));
+        }
+        SubscriptionCmd::Resume(opt) => {
+            exit_on_error(interface::set_subscription_status(
+                pool.clone(),
+                &opt.subscription,
+                SubscriptionStatus::Active,
+                opt.date,
+            ));
+        }
+        SubscriptionCmd::Cancel(opt) => {
+            exit_on_error(interface::set_subscription_status(
+                pool.clone(),
+                &opt.subscription,
+                SubscriptionStatus::Cancelled,
+                opt.date,
+            ));
+        }
+        SubscriptionCmd::Delete(opt) => {
+            exit_on_error(interface::delete_subscription(
+                pool.clone(),
+                &opt.subscription,
+                opt.yes,
+            ));
+        }
+    }
+}
+
+fn account(pool: r2d2::Pool<PostgresConnectionManager<NoTls>>, cmd: AccountCmd) {
+    match cmd {
+        AccountCmd::View(opt) => {
+            let table_vec: Vec<Row> = sql::get_account_values_on(pool.clone(), &opt.date).unwrap();
+            let table_string = interface::account_values_to_table(&table_vec);
+            println!("{}", table_string);
+        }
+        AccountCmd::History { account } => {
+            exit_on_error(interface::account_history(pool.clone(), &account));
+        }
+        AccountCmd::Add => {
+            interface::add_account_prompt(pool.clone());
+        }
+        AccountCmd::Manage => {
+            interface::update_account_values(pool.clone());
+        }
+        AccountCmd::Transfer(opt) => {
+            exit_on_error(interface::transfer_prompt(
+                pool.clone(),
+                opt.date,
+                opt.from,
+                opt.to,
+                opt.amount,
+                opt.note,
+            ));
+        }
+        AccountCmd::Transfers(opt) => {
+            exit_on_error(interface::transfer_history(
+                pool.clone(),
+                opt.account,
+                opt.number,
+            ));
+        }
+        AccountCmd::Track(opt) => {
+            exit_on_error(interface::track_account(
+                pool.clone(),
+                &opt.account,
+                !opt.off,
+                opt.opening_balance,
+                opt.opening_date,
+            ));
+        }
+        AccountCmd::Recompute { account } => {
+            exit_on_error(interface::recompute_accounts(pool.clone(), account));
+        }
+    }
+}
+
+fn portfolio(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    cmd: PortfolioCmd,
+    csv: csv::CsvConfig,
+) {
+    match cmd {
+        PortfolioCmd::View(opt) => {
+            exit_on_error(interface::portfolio_view(pool.clone(), opt.date));
+        }
+        PortfolioCmd::Dates => {
+            let table_vec: Vec<Row> = sql::get_portfolio_dates(pool.clone()).unwrap();
+            let table_string = interface::portfolio_dates_to_table(table_vec);
+            println!("{}", table_string);
+        }
+        PortfolioCmd::Diff(opt) => {
+            exit_on_error(interface::portfolio_diff(pool.clone(), opt.from, opt.to));
+        }
+        PortfolioCmd::Class { item, class } => {
+            exit_on_error(
+                sql::set_item_asset_class(pool.clone(), &item, &class)
+                    .map_err(|e| format!("Could not set asset class: {}", e)),
+            );
+        }
+        PortfolioCmd::Target { class, percent } => {
+            exit_on_error(interface::set_asset_class_target(
+                pool.clone(),
+                &class,
+                &percent,
+            ));
+        }
+        PortfolioCmd::Rebalance(opt) => {
+            exit_on_error(interface::portfolio_rebalance(
+                pool.clone(),
+                opt.contribution,
+            ));
+        }
+        PortfolioCmd::Add(opt) => {
+            let dir = env::current_dir().unwrap();
+            let path = dir.join(opt.file.replace(".\\", ""));
+            let path = path.to_str().unwrap();
+            exit_on_error(
+                csv.select(opt.profile.as_deref(), path)
+                    .and_then(|(name, profile)| {
+                        println!("Using csv profile {}", name);
+                        csv::read_csv(pool.clone(), path, profile, opt.mode, opt.dry_run)
+                            .map_err(|e| format!("Could not add from csv: {}", e))
+                    }),
+            );
+        }
+        PortfolioCmd::Manage(opt) => {
+            exit_on_error(interface::manage_portfolio(
+                pool.clone(),
+                opt.date,
+                opt.id,
+                opt.value,
+            ));
+        }
+    }
+}
+
+fn budget(pool: r2d2::Pool<PostgresConnectionManager<NoTls>>, cmd: BudgetCmd) {
+    match cmd {
+        BudgetCmd::Set(opt) => {
+            exit_on_error(interface::set_budget(
+                pool.clone(),
+                &opt.category,
+                &opt.amount,
+                &opt.period,
+                opt.rollover,
+            ));
+            interface::budget_view(pool.clone(), &Local::today().naive_local());
+        }
+        BudgetCmd::View(opt) => {
+            let date = opt.date.unwrap_or_else(|| Local::today().naive_local());
+            interface::budget_view(pool.clone(), &date);
+        }
+    }
+}
+
+fn category(pool: r2d2::Pool<PostgresConnectionManager<NoTls>>, cmd: CategoryCmd) {
+    match cmd {
+        CategoryCmd::View => {
+            interface::category_view(pool.clone());
+        }
+        CategoryCmd::Add { name, parent } => {
+            exit_on_error(interface::add_category(pool.clone(), &name, parent));
+        }
+        CategoryCmd::Move { category, parent } => {
+            exit_on_error(interface::move_category(pool.clone(), &category, parent));
+        }
+        CategoryCmd::Rename { category, name } => {
+            exit_on_error(interface::rename_category(pool.clone(), &category, &name));
+        }
+        CategoryCmd::Merge(opt) => {
+            exit_on_error(interface::merge_category(
+                pool.clone(),
+                &opt.from,
+                &opt.into,
+                opt.yes,
+            ));
+        }
+        CategoryCmd::Delete(opt) => {
+            exit_on_error(interface::delete_category(
+                pool.clone(),
+                &opt.category,
+                opt.reassign,
+                opt.yes,
+            ));
+        }
+    }
+}
+
+/// Print a user-facing error and exit with a failure status
+fn exit_on_error(result: Result<(), String>) {
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
     }
 }
 
@@ -275,18 +483,13 @@ fn create_config(path: &Path, config_path: &PathBuf) {
             dbuser: "postgres_user".to_string(),
             dbpassword: "postgres_password".to_string(),
         },
-        csv: Csv {
-            currency: "".to_string(),
-            skiprows: 0,
-            stoprows: 0,
-            item_column: 1,
-            value_column: 2,
-        },
+        csv: csv::CsvConfig::default(),
+        networth: NetWorth::default(),
     };
 
     let toml = toml::to_string(&config).unwrap();
-    create_dir_all(&path).expect("Unable to create path");
-    let mut f = File::create(&config_path).expect("Unable to create file");
+    create_dir_all(path).expect("Unable to create path");
+    let mut f = File::create(config_path).expect("Unable to create file");
     f.write_all(toml.as_bytes()).expect("Unable to write data");
 
     println!("A configuration file has been created at {:?}", config_path);
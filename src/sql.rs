@@ -1,9 +1,12 @@
 use chrono::NaiveDate;
-use postgres::{Error, NoTls, Row};
-use r2d2;
+use postgres::types::ToSql;
+use postgres::{Error, NoTls, Row, Transaction};
 use r2d2_postgres::PostgresConnectionManager;
 use rust_decimal::prelude::*;
 
+use crate::csv::Holding;
+use crate::datetime::{BillingInterval, Period};
+
 pub fn get_account_ids(
     pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
 ) -> Result<Vec<Row>, Error> {
@@ -15,128 +18,477 @@ pub fn get_account_ids(
     Ok(rows)
 }
 
+/// Look up an account by id or, failing that, by name (case-insensitive)
+pub fn find_account_id(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    account: &str,
+) -> Result<Option<i32>, Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+        pool.get().unwrap();
+
+    let row = client.query_opt(
+        "SELECT account_id
+         FROM account
+         WHERE account_id::text = $1 OR lower(account_name) = lower($1)
+         ORDER BY account_id::text = $1 DESC
+         LIMIT 1",
+        &[&account],
+    )?;
+
+    Ok(row.map(|row| row.get(0)))
+}
+
+/// Latest value of the account with id `account_id_expr`, or 0 if none has been recorded
+fn latest_account_value(account_id_expr: &str) -> String {
+    format!(
+        "coalesce((SELECT account_value FROM account_value
+                   WHERE account_value.account_id = {}
+                   ORDER BY recorded_at DESC, account_value_id DESC
+                   LIMIT 1), 0)",
+        account_id_expr
+    )
+}
+
+/// Record a new value for an account, `delta` away from its latest one,
+/// for a transaction dated `date`. Accounts that track their balance only
+/// count transactions on or after their opening date, like
+/// `recompute_account_value`. With `tracked_only`, accounts that do not
+/// track their balance from recorded transactions are left alone.
+fn adjust_account_value(
+    transaction: &mut Transaction,
+    account_id: &i32,
+    delta: &Decimal,
+    date: &Option<NaiveDate>,
+    tracked_only: bool,
+) -> Result<(), Error> {
+    let q = format!(
+        "INSERT INTO account_value (account_id, account_value)
+         SELECT account_id, {} + $2
+         FROM account
+         WHERE account_id = $1
+           AND CASE WHEN track_balance THEN opening_date IS NULL OR $4 >= opening_date
+                    ELSE NOT $3 END",
+        latest_account_value("account.account_id")
+    );
+
+    transaction.execute(q.as_str(), &[&account_id, &delta, &tracked_only, &date])?;
+
+    Ok(())
+}
+
+/// Latest value of every account
 pub fn get_account_values(
     pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+) -> Result<Vec<Row>, Error> {
+    get_account_values_on(pool, &None)
+}
+
+/// Value and type of every account as of the end of `date`, or the latest
+/// value if no date is given
+pub fn get_account_values_on(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    date: &Option<NaiveDate>,
 ) -> Result<Vec<Row>, Error> {
     let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
         pool.get().unwrap();
 
     let rows = client.query(
-        "SELECT account.account_id, account_name, coalesce(account_value.account_value, 0)
+        "SELECT account.account_id, account_name, coalesce(latest.account_value, 0),
+                coalesce(account_type.account_type, 'Other')
          FROM account
-         LEFT JOIN account_value
-         ON account.account_id = account_value.account_id
+         LEFT JOIN account_type
+         ON account.account_type_id = account_type.account_type_id
+         LEFT JOIN LATERAL (
+            SELECT account_value
+            FROM account_value
+            WHERE account_value.account_id = account.account_id
+            AND ($1::date IS NULL OR recorded_at < $1::date + 1)
+            ORDER BY recorded_at DESC, account_value_id DESC
+            LIMIT 1
+         ) latest ON true
          ORDER BY account_id",
-        &[],
+        &[&date],
     )?;
 
     Ok(rows)
 }
 
-pub fn get_expense_categories(
+/// Every recorded value of an account: recorded_at, value, change from the
+/// previous value. recorded_at is NULL for a value kept from before values
+/// had a history.
+pub fn get_account_history(
     pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    account_id: &i32,
 ) -> Result<Vec<Row>, Error> {
     let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
         pool.get().unwrap();
 
     let rows = client.query(
-        "SELECT category_id, category_name FROM expense_category",
-        &[],
+        "SELECT CASE WHEN isfinite(recorded_at) THEN recorded_at END, account_value,
+                account_value - lag(account_value) OVER (ORDER BY recorded_at, account_value_id)
+         FROM account_value
+         WHERE account_id = $1
+         ORDER BY recorded_at, account_value_id",
+        &[&account_id],
     )?;
 
     Ok(rows)
 }
 
-pub fn get_expense_num(
+pub fn get_expense_categories(
     pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
-    n: &i64,
 ) -> Result<Vec<Row>, Error> {
     let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
         pool.get().unwrap();
 
-    // Get last n expense
-    let q = "WITH t AS (
-                      SELECT expense.expense_id, expense.date,
-                             account.account_name, to_char(expense.amount, '999999999.00'),
-                             expense_category.category_name, expense.note
-                
-                      FROM expense
-                      LEFT JOIN expense_category
-                      ON expense.category_id = expense_category.category_id
-                      LEFT JOIN account
-                      ON expense.account_id = account.account_id
-                      ORDER BY date
-                      DESC LIMIT $1
-             )
-             SELECT * FROM t ORDER BY date ASC;";
-
-    let rows = client.query(q, &[&n])?;
+    let rows = client.query(
+        "SELECT category_id, category_name, parent_id FROM expense_category",
+        &[],
+    )?;
 
     Ok(rows)
 }
 
-pub fn get_expense_category(
+/// Look up an expense category by id or, failing that, by name (case-insensitive)
+pub fn find_category_id(
     pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
-    n: &i64,
     category: &str,
+) -> Result<Option<i32>, Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+        pool.get().unwrap();
+
+    let row = client.query_opt(
+        "SELECT category_id
+         FROM expense_category
+         WHERE category_id::text = $1 OR lower(category_name) = lower($1)
+         ORDER BY category_id::text = $1 DESC
+         LIMIT 1",
+        &[&category],
+    )?;
+
+    Ok(row.map(|row| row.get(0)))
+}
+
+/// Filters for `get_expenses`. Every filter that is set must match.
+#[derive(Debug, Default)]
+pub struct ExpenseFilter {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    /// Account name or id
+    pub account: Option<String>,
+    /// Category name or id
+    pub category: Option<String>,
+    pub min: Option<Decimal>,
+    pub max: Option<Decimal>,
+    /// Case-insensitive substring of the note
+    pub search: Option<String>,
+    /// Only the latest `limit` matching expenses
+    pub limit: Option<i64>,
+}
+
+impl ExpenseFilter {
+    /// Whether no filter other than the limit is set
+    pub fn is_unfiltered(&self) -> bool {
+        self.from.is_none()
+            && self.to.is_none()
+            && self.account.is_none()
+            && self.category.is_none()
+            && self.min.is_none()
+            && self.max.is_none()
+            && self.search.is_none()
+    }
+}
+
+/// Query for the ids of the categories matching `condition` and all of
+/// their descendants
+fn category_tree_query(condition: &str) -> String {
+    format!(
+        "WITH RECURSIVE tree AS (
+            SELECT category_id FROM expense_category WHERE {}
+            UNION
+            SELECT expense_category.category_id
+            FROM expense_category
+            JOIN tree ON expense_category.parent_id = tree.category_id
+         )
+         SELECT category_id FROM tree",
+        condition
+    )
+}
+
+/// A WHERE clause built from optional conditions, numbering their
+/// parameters in the order they are added
+#[derive(Default)]
+struct WhereClause<'a> {
+    conditions: Vec<String>,
+    params: Vec<&'a (dyn ToSql + Sync)>,
+}
+
+impl<'a> WhereClause<'a> {
+    /// Add `condition` if `value` is set, with every `{}` in it replaced by
+    /// the parameter holding the value
+    fn add<T: ToSql + Sync>(&mut self, value: &'a Option<T>, condition: &str) {
+        if let Some(value) = value {
+            self.params.push(value);
+            let param = format!("${}", self.params.len());
+            self.conditions.push(condition.replace("{}", &param));
+        }
+    }
+
+    fn build(self) -> (String, Vec<&'a (dyn ToSql + Sync)>) {
+        if self.conditions.is_empty() {
+            (String::new(), self.params)
+        } else {
+            (
+                format!("WHERE {}", self.conditions.join(" AND ")),
+                self.params,
+            )
+        }
+    }
+}
+
+/// Condition matching a joined account by id or case-insensitive name
+const ACCOUNT_CONDITION: &str =
+    "(account.account_id::text = {} OR lower(account.account_name) = lower({}))";
+
+/// Build the WHERE clause and its parameters for an expense filter
+fn expense_filter_clause(filter: &ExpenseFilter) -> (String, Vec<&(dyn ToSql + Sync)>) {
+    let mut clause = WhereClause::default();
+    clause.add(&filter.from, "expense.date >= {}");
+    clause.add(&filter.to, "expense.date <= {}");
+    clause.add(&filter.account, ACCOUNT_CONDITION);
+    clause.add(
+        &filter.category,
+        &format!(
+            "expense.category_id IN ({})",
+            category_tree_query("category_id::text = {} OR lower(category_name) = lower({})")
+        ),
+    );
+    clause.add(&filter.min, "expense.amount >= {}");
+    clause.add(&filter.max, "expense.amount <= {}");
+    clause.add(&filter.search, "expense.note ILIKE '%' || {} || '%'");
+    clause.build()
+}
+
+/// Get the latest expenses matching the filter, oldest first
+pub fn get_expenses(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    filter: &ExpenseFilter,
 ) -> Result<Vec<Row>, Error> {
     let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
         pool.get().unwrap();
 
-    let q = "WITH t AS (
+    let (where_clause, mut params) = expense_filter_clause(filter);
+    params.push(&filter.limit);
+
+    let q = format!(
+        "WITH t AS (
             SELECT expense.expense_id, expense.date,
                    account.account_name, to_char(expense.amount, '999999999.00'),
                    expense_category.category_name, expense.note
-      
             FROM expense
             LEFT JOIN expense_category
             ON expense.category_id = expense_category.category_id
             LEFT JOIN account
             ON expense.account_id = account.account_id
-            WHERE expense_category.category_name = $1
-            ORDER BY date
-            DESC LIMIT $2
-   )
-   SELECT * FROM t ORDER BY date ASC;";
+            {}
+            ORDER BY expense.date DESC, expense.expense_id DESC
+            LIMIT ${}
+         )
+         SELECT * FROM t ORDER BY date ASC, expense_id ASC;",
+        where_clause,
+        params.len()
+    );
 
-    let rows = client.query(q, &[&category, &n])?;
+    let rows = client.query(q.as_str(), &params)?;
 
     Ok(rows)
 }
 
-pub fn expense_category_count(
+/// Total spent directly in each category per period: category_id, period start, total
+pub fn get_expense_totals(
     pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
-    category: &str,
-) -> Result<i64, Error> {
+    filter: &ExpenseFilter,
+    period: &Period,
+) -> Result<Vec<Row>, Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+        pool.get().unwrap();
+
+    let (where_clause, mut params) = expense_filter_clause(filter);
+    let trunc = period.trunc_field();
+    params.push(&trunc);
+
+    let q = format!(
+        "SELECT expense.category_id,
+                date_trunc(${}, expense.date)::date as period,
+                SUM(expense.amount) as total
+         FROM expense
+         LEFT JOIN expense_category
+         ON expense.category_id = expense_category.category_id
+         LEFT JOIN account
+         ON expense.account_id = account.account_id
+         {}
+         GROUP BY 1, 2
+         ORDER BY 2, 1",
+        params.len(),
+        where_clause
+    );
+
+    let rows = client.query(q.as_str(), &params)?;
+
+    Ok(rows)
+}
+
+/// Get a single expense formatted like `get_expenses`
+pub fn get_expense(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    id: &i32,
+) -> Result<Vec<Row>, Error> {
     let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
         pool.get().unwrap();
 
-    let q = "SELECT COUNT(*)
+    let q = "SELECT expense.expense_id, expense.date,
+                    account.account_name, to_char(expense.amount, '999999999.00'),
+                    expense_category.category_name, expense.note
              FROM expense
              LEFT JOIN expense_category
              ON expense.category_id = expense_category.category_id
-             WHERE expense_category.category_name = $1";
+             LEFT JOIN account
+             ON expense.account_id = account.account_id
+             WHERE expense.expense_id = $1";
 
-    let row = client.query_one(q, &[&category])?;
-    let count: i64 = row.get("count");
-    Ok(count)
+    let rows = client.query(q, &[&id])?;
+
+    Ok(rows)
+}
+
+/// Get the raw column values of an expense: date, account_id, amount, category_id, note
+pub fn get_expense_values(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    id: &i32,
+) -> Result<Option<Row>, Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+        pool.get().unwrap();
+
+    let row = client.query_opt(
+        "SELECT date, account_id, amount, category_id, coalesce(note, '')
+         FROM expense
+         WHERE expense_id = $1",
+        &[&id],
+    )?;
+
+    Ok(row)
+}
+
+pub fn update_expense(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    id: &i32,
+    date: &NaiveDate,
+    account_id: &i32,
+    expense_value: &Decimal,
+    category_id: &i32,
+    note: String,
+) -> Result<u64, Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+        pool.get().unwrap();
+
+    let mut transaction = client.transaction()?;
+
+    let old = transaction.query_opt(
+        "SELECT account_id, amount, date FROM expense WHERE expense_id = $1",
+        &[&id],
+    )?;
+    if let Some(old) = &old {
+        let old_account_id: Option<i32> = old.get(0);
+        let old_value: Option<Decimal> = old.get(1);
+        let old_date: Option<NaiveDate> = old.get(2);
+        if let (Some(old_account_id), Some(old_value)) = (old_account_id, old_value) {
+            adjust_account_value(
+                &mut transaction,
+                &old_account_id,
+                &old_value,
+                &old_date,
+                true,
+            )?;
+        }
+    }
+
+    let rows_updated = transaction.execute(
+        "UPDATE expense
+         SET date = $2, account_id = $3, amount = $4, category_id = $5, note = $6
+         WHERE expense_id = $1",
+        &[&id, &date, &account_id, &expense_value, &category_id, &note],
+    )?;
+    if rows_updated > 0 {
+        adjust_account_value(
+            &mut transaction,
+            account_id,
+            &-*expense_value,
+            &Some(*date),
+            true,
+        )?;
+    }
+
+    transaction.commit()?;
+
+    Ok(rows_updated)
+}
+
+pub fn delete_expense(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    id: &i32,
+) -> Result<u64, Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+        pool.get().unwrap();
+
+    let mut transaction = client.transaction()?;
+
+    let deleted = transaction.query_opt(
+        "DELETE FROM expense WHERE expense_id = $1 RETURNING account_id, amount, date",
+        &[&id],
+    )?;
+    if let Some(deleted) = &deleted {
+        let account_id: Option<i32> = deleted.get(0);
+        let expense_value: Option<Decimal> = deleted.get(1);
+        let date: Option<NaiveDate> = deleted.get(2);
+        if let (Some(account_id), Some(expense_value)) = (account_id, expense_value) {
+            adjust_account_value(&mut transaction, &account_id, &expense_value, &date, true)?;
+        }
+    }
+
+    transaction.commit()?;
+
+    Ok(deleted.map_or(0, |_| 1))
 }
 
+/// Subscriptions with their latest price change. Paused and cancelled
+/// subscriptions are only included if `all` is set.
 pub fn get_subscriptions(
     pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    all: bool,
 ) -> Result<Vec<Row>, Error> {
     let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
         pool.get().unwrap();
 
     let rows = client.query(
-        "SELECT subscription.subscription_name, expense_category.category_name,
-                subscription.subscription_price as yearly,
-                to_char(subscription.subscription_price / 12, '990D99') as monthly
+        "SELECT subscription.subscription_id, subscription.subscription_name,
+                expense_category.category_name, subscription.subscription_price,
+                subscription.billing_interval, subscription.start_date,
+                subscription.account_id, subscription.category_id,
+                subscription.status, subscription.status_date,
+                latest.changed_on, latest.old_price, latest.old_billing_interval
          FROM subscription
          JOIN expense_category
          ON subscription.category_id = expense_category.category_id
-         ORDER BY subscription.subscription_price DESC",
-        &[],
+         LEFT JOIN LATERAL (
+             SELECT changed_on, old_price, old_billing_interval
+             FROM subscription_price_change
+             WHERE subscription_price_change.subscription_id = subscription.subscription_id
+             ORDER BY changed_on DESC, price_change_id DESC
+             LIMIT 1
+         ) latest ON true
+         WHERE $1 OR subscription.status = 'active'
+         ORDER BY subscription.subscription_name",
+        &[&all],
     )?;
 
     Ok(rows)
@@ -153,13 +505,44 @@ pub fn add_expense(
     let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
         pool.get().unwrap();
 
-    client.execute(
+    let mut transaction = client.transaction()?;
+    insert_expense(
+        &mut transaction,
+        date,
+        account_id,
+        expense_value,
+        category_id,
+        &note,
+    )?;
+    transaction.commit()?;
+
+    Ok(())
+}
+
+/// Insert an expense and take it off the account's value if tracked
+fn insert_expense(
+    transaction: &mut Transaction,
+    date: &NaiveDate,
+    account_id: &i32,
+    expense_value: &Decimal,
+    category_id: &i32,
+    note: &str,
+) -> Result<i32, Error> {
+    let row = transaction.query_one(
         "INSERT INTO expense (expense_id, date, account_id, amount, category_id, note)
-        VALUES (DEFAULT, $1, $2, $3, $4, $5)",
+        VALUES (DEFAULT, $1, $2, $3, $4, $5)
+        RETURNING expense_id",
         &[&date, &account_id, &expense_value, &category_id, &note],
     )?;
+    adjust_account_value(
+        transaction,
+        account_id,
+        &-*expense_value,
+        &Some(*date),
+        true,
+    )?;
 
-    Ok(())
+    Ok(row.get(0))
 }
 
 pub fn add_subscription(
@@ -167,189 +550,686 @@ pub fn add_subscription(
     subscription_name: String,
     category_id: i32,
     expense_value: Decimal,
+    billing_interval: &BillingInterval,
+    start_date: &NaiveDate,
+    account_id: &Option<i32>,
 ) -> Result<(), Error> {
     let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
         pool.get().unwrap();
 
     client.execute(
-        "INSERT INTO subscription (subscription_id, subscription_name, category_id, subscription_price)
-        VALUES (DEFAULT, $1, $2, $3)",
-        &[&subscription_name, &category_id, &expense_value],
+        "INSERT INTO subscription (subscription_id, subscription_name, category_id, subscription_price,
+                                   billing_interval, start_date, account_id)
+        VALUES (DEFAULT, $1, $2, $3, $4, $5, $6)",
+        &[
+            &subscription_name,
+            &category_id,
+            &expense_value,
+            &billing_interval.to_string(),
+            &start_date,
+            &account_id,
+        ],
     )?;
 
     Ok(())
 }
 
-pub fn update_account_value(
+pub fn find_subscription_id(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    subscription: &str,
+) -> Result<Option<i32>, Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+        pool.get().unwrap();
+
+    let row = client.query_opt(
+        "SELECT subscription_id
+         FROM subscription
+         WHERE subscription_id::text = $1 OR lower(subscription_name) = lower($1)
+         ORDER BY subscription_id::text = $1 DESC
+         LIMIT 1",
+        &[&subscription],
+    )?;
+
+    Ok(row.map(|row| row.get(0)))
+}
+
+/// Editable fields of a subscription: name, category id, price, billing
+/// interval, start date and account id
+pub fn get_subscription_values(
     pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
-    value: &Decimal,
     id: &i32,
-) -> Result<u64, Error> {
+) -> Result<Option<Row>, Error> {
     let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
         pool.get().unwrap();
 
-    let rows_updated = client.execute(
-        "UPDATE account_value SET account_value = $1 WHERE account_id = $2",
-        &[&value, &id],
+    let row = client.query_opt(
+        "SELECT subscription_name, category_id, subscription_price, billing_interval,
+                start_date, account_id, status, status_date
+         FROM subscription
+         WHERE subscription_id = $1",
+        &[&id],
     )?;
 
-    Ok(rows_updated)
+    Ok(row)
 }
 
-pub fn get_portfolio_sum(pool: r2d2::Pool<PostgresConnectionManager<NoTls>>) -> Result<Row, Error> {
+/// Update a subscription, recording a price change if the price or billing
+/// interval differs
+#[allow(clippy::too_many_arguments)]
+pub fn update_subscription(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    id: &i32,
+    subscription_name: &str,
+    category_id: &i32,
+    price: &Decimal,
+    billing_interval: &BillingInterval,
+    start_date: &Option<NaiveDate>,
+    account_id: &Option<i32>,
+) -> Result<(), Error> {
     let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
         pool.get().unwrap();
 
-    let rows = client.query_one(
-        "SELECT  0 as id, 'Total' as item, SUM(value), '' as proportion
-     FROM portfolio
-     WHERE date = (select max (date) from  portfolio)",
-        &[],
+    let mut transaction = client.transaction()?;
+
+    let old = transaction.query_one(
+        "SELECT subscription_price, billing_interval
+         FROM subscription
+         WHERE subscription_id = $1
+         FOR UPDATE",
+        &[&id],
+    )?;
+    let old_price: Decimal = old.get(0);
+    let old_interval: String = old.get(1);
+    if old_price != *price || old_interval != billing_interval.to_string() {
+        transaction.execute(
+            "INSERT INTO subscription_price_change
+                 (subscription_id, changed_on, old_price, old_billing_interval, new_price)
+             VALUES ($1, CURRENT_DATE, $2, $3, $4)",
+            &[&id, &old_price, &old_interval, &price],
+        )?;
+    }
+
+    transaction.execute(
+        "UPDATE subscription
+         SET subscription_name = $2, category_id = $3, subscription_price = $4,
+             billing_interval = $5, start_date = $6, account_id = $7
+         WHERE subscription_id = $1",
+        &[
+            &id,
+            &subscription_name,
+            &category_id,
+            &price,
+            &billing_interval.to_string(),
+            &start_date,
+            &account_id,
+        ],
     )?;
 
-    Ok(rows)
+    transaction.commit()?;
+
+    Ok(())
 }
 
-pub fn get_subscriptions_sum(
+/// Mark a subscription active, paused or cancelled as of `date`, recording
+/// the change in the status history
+pub fn set_subscription_status(
     pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
-) -> Result<Row, Error> {
+    id: &i32,
+    status: &str,
+    date: &NaiveDate,
+) -> Result<u64, Error> {
     let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
         pool.get().unwrap();
 
-    let rows = client.query_one(
-        "SELECT 'Total' as subscription_name,
-         '' as category_name,
-         SUM(subscription_price) as yearly,
-         to_char(SUM(subscription_price)/12, '990D99') as monthly
-         FROM subscription",
-        &[],
+    let mut transaction = client.transaction()?;
+
+    let rows_updated = transaction.execute(
+        "UPDATE subscription SET status = $2, status_date = $3 WHERE subscription_id = $1",
+        &[&id, &status, &date],
+    )?;
+    transaction.execute(
+        "INSERT INTO subscription_status_change (subscription_id, changed_on, status)
+         VALUES ($1, $2, $3)",
+        &[&id, &date, &status],
     )?;
 
-    Ok(rows)
+    transaction.commit()?;
+
+    Ok(rows_updated)
 }
 
-pub fn get_portfolio(
+/// Status history of a subscription, oldest first: changed_on, status
+pub fn get_subscription_status_changes(
     pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    subscription_id: &i32,
 ) -> Result<Vec<Row>, Error> {
     let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
         pool.get().unwrap();
 
     let rows = client.query(
-        "SELECT portfolio_id, item, value, to_char(100 * (value / sum(value) over ()), '990D99%') as proportion
-         FROM portfolio
-         WHERE date = (select max (date) from  portfolio)
-         ORDER BY value DESC",
-        &[],
+        "SELECT changed_on, status
+         FROM subscription_status_change
+         WHERE subscription_id = $1
+         ORDER BY changed_on, status_change_id",
+        &[&subscription_id],
     )?;
 
     Ok(rows)
 }
 
-pub fn check_portfolio(
+/// Delete a subscription with its price history and posting records. Expenses
+/// already posted from it are kept.
+pub fn delete_subscription(
     pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
-    date: &NaiveDate,
-    item: &str,
-    value: &Decimal,
-) -> Result<bool, Error> {
+    id: &i32,
+) -> Result<u64, Error> {
     let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
         pool.get().unwrap();
 
-    let rows = client.query_one(
-        "SELECT COUNT(*) > 0
-        FROM portfolio
-        WHERE date = $1 AND item = $2 AND value = $3",
-        &[&date, &item, &value],
-    );
+    let mut transaction = client.transaction()?;
 
-    let mut exists = false;
-    for row in rows {
-        exists = row.get(0);
-    }
+    transaction.execute(
+        "DELETE FROM subscription_price_change WHERE subscription_id = $1",
+        &[&id],
+    )?;
+    transaction.execute(
+        "DELETE FROM subscription_status_change WHERE subscription_id = $1",
+        &[&id],
+    )?;
+    transaction.execute(
+        "DELETE FROM subscription_posting WHERE subscription_id = $1",
+        &[&id],
+    )?;
+    let rows_deleted = transaction.execute(
+        "DELETE FROM subscription WHERE subscription_id = $1",
+        &[&id],
+    )?;
 
-    Ok(exists)
+    transaction.commit()?;
+
+    Ok(rows_deleted)
 }
 
-pub fn insert_portfolio(
+/// Renewal dates of a subscription that have already been posted
+pub fn get_posted_renewals(
     pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
-    date: &NaiveDate,
-    item: &str,
-    value: &Decimal,
-) -> Result<(), Error> {
+    subscription_id: &i32,
+) -> Result<Vec<NaiveDate>, Error> {
     let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
         pool.get().unwrap();
 
-    client.execute(
-        "INSERT INTO portfolio (portfolio_id, date, item, value)
-        VALUES (DEFAULT, $1, $2, $3)",
-        &[&date, &item, &value],
+    let rows = client.query(
+        "SELECT renewal_date FROM subscription_posting WHERE subscription_id = $1",
+        &[&subscription_id],
     )?;
 
-    Ok(())
+    Ok(rows.iter().map(|row| row.get(0)).collect())
 }
 
-pub fn portfolio_count(
+/// Date `subscription post` last ran, or the latest renewal it posted if
+/// no run has been recorded
+pub fn get_last_post_run(
     pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
-) -> Result<String, Error> {
+) -> Result<Option<NaiveDate>, Error> {
     let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
         pool.get().unwrap();
 
     let row = client.query_one(
-        "SELECT 'Count: ' || COUNT(*) as count
-         FROM portfolio
-         WHERE date = (select max (date) from  portfolio)",
+        "SELECT coalesce((SELECT max(ran_on) FROM subscription_post_run),
+                         (SELECT max(renewal_date) FROM subscription_posting))",
         &[],
     )?;
 
-    let count = row.get("count");
+    Ok(row.get(0))
+}
 
-    Ok(count)
+pub fn record_post_run(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    date: &NaiveDate,
+) -> Result<(), Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+        pool.get().unwrap();
+
+    client.execute(
+        "INSERT INTO subscription_post_run (ran_on) VALUES ($1)",
+        &[&date],
+    )?;
+
+    Ok(())
 }
 
-pub fn get_account_types(
+/// Price changes of a subscription, oldest first: changed_on, old_price,
+/// old_billing_interval
+pub fn get_subscription_price_changes(
     pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    subscription_id: &i32,
 ) -> Result<Vec<Row>, Error> {
     let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
         pool.get().unwrap();
 
     let rows = client.query(
-        "SELECT account_type_id, account_type FROM account_type",
-        &[],
+        "SELECT changed_on, old_price, old_billing_interval
+         FROM subscription_price_change
+         WHERE subscription_id = $1
+         ORDER BY changed_on, price_change_id",
+        &[&subscription_id],
     )?;
 
     Ok(rows)
 }
 
-pub fn add_account(
+/// Record a subscription renewal as an expense. Returns the new expense id,
+/// or None if the renewal had already been posted.
+pub fn post_subscription_renewal(
     pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
-    account_name: String,
-    account_type_id: i32,
-    account_value: Decimal,
-) -> Result<(), Error> {
+    subscription_id: &i32,
+    renewal_date: &NaiveDate,
+    account_id: &i32,
+    expense_value: &Decimal,
+    category_id: &i32,
+    note: &str,
+) -> Result<Option<i32>, Error> {
     let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
         pool.get().unwrap();
 
-    client.execute(
-        "INSERT INTO account (account_id, account_name, account_type_id)
-         VALUES (DEFAULT, $1, $2)",
-        &[&account_name, &account_type_id],
-    )?;
+    let mut transaction = client.transaction()?;
 
-    let account_id = account_id_from_name(pool.clone(), account_name)?;
+    let claimed = transaction.execute(
+        "INSERT INTO subscription_posting (subscription_id, renewal_date)
+         VALUES ($1, $2)
+         ON CONFLICT DO NOTHING",
+        &[&subscription_id, &renewal_date],
+    )?;
+    if claimed == 0 {
+        return Ok(None);
+    }
 
-    client.execute(
-        "INSERT INTO account_value (account_id, account_value)
-         VALUES ($1, $2)",
-        &[&account_id, &account_value],
+    let expense_id = insert_expense(
+        &mut transaction,
+        renewal_date,
+        account_id,
+        expense_value,
+        category_id,
+        note,
+    )?;
+    transaction.execute(
+        "UPDATE subscription_posting SET expense_id = $3
+         WHERE subscription_id = $1 AND renewal_date = $2",
+        &[&subscription_id, &renewal_date, &expense_id],
     )?;
 
-    Ok(())
+    transaction.commit()?;
+
+    Ok(Some(expense_id))
 }
 
-fn account_id_from_name(
+pub fn update_account_value(
     pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
-    account_name: String,
-) -> Result<i32, Error> {
-    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+    value: &Decimal,
+    id: &i32,
+) -> Result<u64, Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+        pool.get().unwrap();
+
+    let rows_updated = client.execute(
+        "INSERT INTO account_value (account_id, account_value)
+         SELECT account_id, $1 FROM account WHERE account_id = $2",
+        &[&value, &id],
+    )?;
+
+    Ok(rows_updated)
+}
+
+/// Date of the latest portfolio snapshot taken on or before `date`, or of
+/// the latest snapshot if no date is given
+pub fn get_portfolio_date(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    date: &Option<NaiveDate>,
+) -> Result<Option<NaiveDate>, Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+        pool.get().unwrap();
+
+    let row = client.query_one(
+        "SELECT max(date) FROM portfolio WHERE $1::date IS NULL OR date <= $1",
+        &[&date],
+    )?;
+
+    Ok(row.get(0))
+}
+
+/// Every portfolio snapshot date with its number of items and total value
+pub fn get_portfolio_dates(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+) -> Result<Vec<Row>, Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+        pool.get().unwrap();
+
+    let rows = client.query(
+        "SELECT date, COUNT(*), SUM(value)
+         FROM portfolio
+         GROUP BY date
+         ORDER BY date DESC",
+        &[],
+    )?;
+
+    Ok(rows)
+}
+
+/// Value of each item in two snapshots. Items missing from one of them
+/// have a NULL value there.
+pub fn get_portfolio_diff(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    from: &NaiveDate,
+    to: &NaiveDate,
+) -> Result<Vec<Row>, Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+        pool.get().unwrap();
+
+    let rows = client.query(
+        "SELECT coalesce(old.item, new.item), old.value, new.value
+         FROM (SELECT item, SUM(value) as value FROM portfolio WHERE date = $1 GROUP BY item) old
+         FULL OUTER JOIN
+              (SELECT item, SUM(value) as value FROM portfolio WHERE date = $2 GROUP BY item) new
+         ON old.item = new.item
+         ORDER BY 1",
+        &[&from, &to],
+    )?;
+
+    Ok(rows)
+}
+
+pub fn get_portfolio_sum(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    date: &NaiveDate,
+) -> Result<Row, Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+        pool.get().unwrap();
+
+    let rows = client.query_one(
+        "SELECT  0 as id, 'Total' as item, SUM(value), '' as proportion,
+             NULL::numeric as quantity, NULL::numeric as price,
+             CASE WHEN COUNT(cost_basis) = COUNT(*) THEN SUM(cost_basis) END as cost_basis
+     FROM portfolio
+     WHERE date = $1",
+        &[&date],
+    )?;
+
+    Ok(rows)
+}
+
+/// Total of the latest portfolio snapshot taken on or before `date`
+pub fn get_portfolio_total_on(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    date: &NaiveDate,
+) -> Result<Decimal, Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+        pool.get().unwrap();
+
+    let row = client.query_one(
+        "SELECT coalesce(SUM(value), 0)
+         FROM portfolio
+         WHERE date = (SELECT max(date) FROM portfolio WHERE date <= $1)",
+        &[&date],
+    )?;
+
+    Ok(row.get(0))
+}
+
+pub fn get_portfolio(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    date: &NaiveDate,
+) -> Result<Vec<Row>, Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+        pool.get().unwrap();
+
+    let rows = client.query(
+        "SELECT portfolio_id, item, value, to_char(100 * (value / sum(value) over ()), '990D99%') as proportion,
+                quantity, price, cost_basis
+         FROM portfolio
+         WHERE date = $1
+         ORDER BY value DESC",
+        &[&date],
+    )?;
+
+    Ok(rows)
+}
+
+/// Write an import into the snapshot on `date` in one transaction, so
+/// either every change is made or none is. `upserts` are added, or replace
+/// the item already in the snapshot, and the items in `removes` are deleted.
+pub fn import_portfolio(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    date: &NaiveDate,
+    upserts: &[&Holding],
+    removes: &[&str],
+) -> Result<(), Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+        pool.get().unwrap();
+
+    let items: Vec<&str> = upserts.iter().map(|h| h.item.as_str()).collect();
+    let values: Vec<Decimal> = upserts.iter().map(|h| h.value).collect();
+    let quantities: Vec<Option<Decimal>> = upserts.iter().map(|h| h.quantity).collect();
+    let prices: Vec<Option<Decimal>> = upserts.iter().map(|h| h.price).collect();
+    let costs: Vec<Option<Decimal>> = upserts.iter().map(|h| h.cost).collect();
+
+    let mut transaction = client.transaction()?;
+    transaction.execute(
+        "DELETE FROM portfolio WHERE date = $1 AND item = ANY($2::varchar[])",
+        &[&date, &removes],
+    )?;
+    transaction.execute(
+        "INSERT INTO portfolio (date, item, value, quantity, price, cost_basis)
+        SELECT $1, new.item, new.value, new.quantity, new.price, new.cost_basis
+        FROM unnest($2::varchar[], $3::numeric[], $4::numeric[], $5::numeric[], $6::numeric[])
+            AS new(item, value, quantity, price, cost_basis)
+        ON CONFLICT (date, item) DO UPDATE
+        SET value = excluded.value, quantity = excluded.quantity,
+            price = excluded.price, cost_basis = excluded.cost_basis",
+        &[&date, &items, &values, &quantities, &prices, &costs],
+    )?;
+    transaction.commit()?;
+
+    Ok(())
+}
+
+/// Item and value of the portfolio row `id`, if it is in the snapshot on `date`
+pub fn get_portfolio_item(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    id: &i32,
+    date: &NaiveDate,
+) -> Result<Option<Row>, Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+        pool.get().unwrap();
+
+    let row = client.query_opt(
+        "SELECT item, value FROM portfolio WHERE portfolio_id = $1 AND date = $2",
+        &[&id, &date],
+    )?;
+
+    Ok(row)
+}
+
+/// Set the value of a portfolio row, working the price out again from the
+/// quantity when there is one
+pub fn update_portfolio_value(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    id: &i32,
+    value: &Decimal,
+) -> Result<u64, Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+        pool.get().unwrap();
+
+    let updated = client.execute(
+        "UPDATE portfolio
+        SET value = $2,
+            price = CASE WHEN quantity <> 0 THEN $2 / quantity ELSE price END
+        WHERE portfolio_id = $1",
+        &[&id, &value],
+    )?;
+
+    Ok(updated)
+}
+
+pub fn delete_portfolio_item(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    id: &i32,
+) -> Result<u64, Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+        pool.get().unwrap();
+
+    let deleted = client.execute("DELETE FROM portfolio WHERE portfolio_id = $1", &[&id])?;
+
+    Ok(deleted)
+}
+
+pub fn portfolio_count(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    date: &NaiveDate,
+) -> Result<String, Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+        pool.get().unwrap();
+
+    let row = client.query_one(
+        "SELECT 'Count: ' || COUNT(*) as count
+         FROM portfolio
+         WHERE date = $1",
+        &[&date],
+    )?;
+
+    let count = row.get("count");
+
+    Ok(count)
+}
+
+/// Set the target percentage of an asset class, creating it if needed
+pub fn set_asset_class_target(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    name: &str,
+    target: &Decimal,
+) -> Result<(), Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+        pool.get().unwrap();
+
+    client.execute(
+        "INSERT INTO asset_class (name, target)
+         VALUES ($1, $2)
+         ON CONFLICT (name)
+         DO UPDATE SET target = $2",
+        &[&name, &target],
+    )?;
+
+    Ok(())
+}
+
+/// Put a portfolio item in an asset class, creating the class if needed
+pub fn set_item_asset_class(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    item: &str,
+    name: &str,
+) -> Result<(), Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+        pool.get().unwrap();
+
+    let mut transaction = client.transaction()?;
+
+    transaction.execute(
+        "INSERT INTO asset_class (name) VALUES ($1) ON CONFLICT (name) DO NOTHING",
+        &[&name],
+    )?;
+    transaction.execute(
+        "INSERT INTO portfolio_item_class (item, asset_class_id)
+         SELECT $1, asset_class_id FROM asset_class WHERE name = $2
+         ON CONFLICT (item)
+         DO UPDATE SET asset_class_id = excluded.asset_class_id",
+        &[&item, &name],
+    )?;
+
+    transaction.commit()?;
+
+    Ok(())
+}
+
+/// Asset classes with their target percentage, NULL if none has been set
+pub fn get_asset_class_targets(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+) -> Result<Vec<Row>, Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+        pool.get().unwrap();
+
+    let rows = client.query("SELECT name, target FROM asset_class ORDER BY name", &[])?;
+
+    Ok(rows)
+}
+
+/// Total value of each asset class in the snapshot taken on `date`. Items
+/// without a class are grouped as NULL.
+pub fn get_asset_class_values(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    date: &NaiveDate,
+) -> Result<Vec<Row>, Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+        pool.get().unwrap();
+
+    let rows = client.query(
+        "SELECT asset_class.name, SUM(portfolio.value)
+         FROM portfolio
+         LEFT JOIN portfolio_item_class
+         ON portfolio.item = portfolio_item_class.item
+         LEFT JOIN asset_class
+         ON portfolio_item_class.asset_class_id = asset_class.asset_class_id
+         WHERE portfolio.date = $1
+         GROUP BY asset_class.name",
+        &[&date],
+    )?;
+
+    Ok(rows)
+}
+
+pub fn get_account_types(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+) -> Result<Vec<Row>, Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+        pool.get().unwrap();
+
+    let rows = client.query(
+        "SELECT account_type_id, account_type FROM account_type",
+        &[],
+    )?;
+
+    Ok(rows)
+}
+
+pub fn add_account(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    account_name: String,
+    account_type_id: i32,
+    account_value: Decimal,
+) -> Result<(), Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+        pool.get().unwrap();
+
+    client.execute(
+        "INSERT INTO account (account_id, account_name, account_type_id)
+         VALUES (DEFAULT, $1, $2)",
+        &[&account_name, &account_type_id],
+    )?;
+
+    let account_id = account_id_from_name(pool.clone(), account_name)?;
+
+    client.execute(
+        "INSERT INTO account_value (account_id, account_value)
+         VALUES ($1, $2)",
+        &[&account_id, &account_value],
+    )?;
+
+    Ok(())
+}
+
+fn account_id_from_name(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    account_name: String,
+) -> Result<i32, Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
         pool.get().unwrap();
 
     let q = "SELECT *
@@ -360,3 +1240,570 @@ fn account_id_from_name(
     let id: i32 = row.get("account_id");
     Ok(id)
 }
+
+/// Create or change the budget of a category. A new budget, or one whose
+/// period changes, starts on `start_date`.
+pub fn set_budget(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    category_id: &i32,
+    period: &Period,
+    amount: &Decimal,
+    rollover: bool,
+    start_date: &NaiveDate,
+) -> Result<(), Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+        pool.get().unwrap();
+
+    client.execute(
+        "INSERT INTO budget (budget_id, category_id, period, amount, rollover, start_date)
+         VALUES (DEFAULT, $1, $2, $3, $4, $5)
+         ON CONFLICT (category_id)
+         DO UPDATE SET period = $2, amount = $3, rollover = $4,
+             start_date = CASE WHEN budget.period = $2 THEN budget.start_date ELSE $5 END",
+        &[
+            &category_id,
+            &period.to_string(),
+            &amount,
+            &rollover,
+            &start_date,
+        ],
+    )?;
+
+    Ok(())
+}
+
+/// Budgets with their category: category_id, category_name, period, amount,
+/// rollover, start_date
+pub fn get_budgets(pool: r2d2::Pool<PostgresConnectionManager<NoTls>>) -> Result<Vec<Row>, Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+        pool.get().unwrap();
+
+    let rows = client.query(
+        "SELECT budget.category_id, expense_category.category_name,
+                budget.period, budget.amount, budget.rollover, budget.start_date
+         FROM budget
+         JOIN expense_category
+         ON budget.category_id = expense_category.category_id
+         ORDER BY expense_category.category_name",
+        &[],
+    )?;
+
+    Ok(rows)
+}
+
+/// Budget of a single category, with the same columns as `get_budgets`
+pub fn get_budget(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    category_id: &i32,
+) -> Result<Option<Row>, Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+        pool.get().unwrap();
+
+    let row = client.query_opt(
+        "SELECT budget.category_id, expense_category.category_name,
+                budget.period, budget.amount, budget.rollover, budget.start_date
+         FROM budget
+         JOIN expense_category
+         ON budget.category_id = expense_category.category_id
+         WHERE budget.category_id = $1",
+        &[&category_id],
+    )?;
+
+    Ok(row)
+}
+
+/// Total spent in a category and its descendants from `from` up to but not
+/// including `until`
+pub fn get_category_spent(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    category_id: &i32,
+    from: &NaiveDate,
+    until: &NaiveDate,
+) -> Result<Decimal, Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+        pool.get().unwrap();
+
+    let q = format!(
+        "SELECT coalesce(SUM(amount), 0)
+         FROM expense
+         WHERE category_id IN ({}) AND date >= $2 AND date < $3",
+        category_tree_query("category_id = $1")
+    );
+
+    let row = client.query_one(q.as_str(), &[&category_id, &from, &until])?;
+
+    Ok(row.get(0))
+}
+
+pub fn add_category(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    category_name: &str,
+    parent_id: &Option<i32>,
+) -> Result<(), Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+        pool.get().unwrap();
+
+    client.execute(
+        "INSERT INTO expense_category (category_id, category_name, parent_id)
+         VALUES (DEFAULT, $1, $2)",
+        &[&category_name, &parent_id],
+    )?;
+
+    Ok(())
+}
+
+pub fn set_category_parent(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    category_id: &i32,
+    parent_id: &Option<i32>,
+) -> Result<u64, Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+        pool.get().unwrap();
+
+    let rows_updated = client.execute(
+        "UPDATE expense_category SET parent_id = $2 WHERE category_id = $1",
+        &[&category_id, &parent_id],
+    )?;
+
+    Ok(rows_updated)
+}
+
+/// Ids of a category and all of its descendants
+pub fn get_category_descendants(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    category_id: &i32,
+) -> Result<Vec<i32>, Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+        pool.get().unwrap();
+
+    let rows = client.query(
+        category_tree_query("category_id = $1").as_str(),
+        &[&category_id],
+    )?;
+
+    Ok(rows.iter().map(|row| row.get(0)).collect())
+}
+
+pub fn rename_category(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    category_id: &i32,
+    category_name: &str,
+) -> Result<u64, Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+        pool.get().unwrap();
+
+    let rows_updated = client.execute(
+        "UPDATE expense_category SET category_name = $2 WHERE category_id = $1",
+        &[&category_id, &category_name],
+    )?;
+
+    Ok(rows_updated)
+}
+
+/// Number of expenses, subscriptions and subcategories that use a category
+pub fn category_usage(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    category_id: &i32,
+) -> Result<(i64, i64, i64), Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+        pool.get().unwrap();
+
+    let row = client.query_one(
+        "SELECT (SELECT COUNT(*) FROM expense WHERE category_id = $1),
+                (SELECT COUNT(*) FROM subscription WHERE category_id = $1),
+                (SELECT COUNT(*) FROM expense_category WHERE parent_id = $1)",
+        &[&category_id],
+    )?;
+
+    Ok((row.get(0), row.get(1), row.get(2)))
+}
+
+/// Move every expense, subscription and subcategory from one category to
+/// another and delete the old category, all in one transaction. The old category's
+/// budget is kept only if the new category has none.
+pub fn merge_category(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    from_id: &i32,
+    to_id: &i32,
+) -> Result<(u64, u64), Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+        pool.get().unwrap();
+    let mut transaction = client.transaction()?;
+
+    let expenses = transaction.execute(
+        "UPDATE expense SET category_id = $2 WHERE category_id = $1",
+        &[&from_id, &to_id],
+    )?;
+    let subscriptions = transaction.execute(
+        "UPDATE subscription SET category_id = $2 WHERE category_id = $1",
+        &[&from_id, &to_id],
+    )?;
+    transaction.execute(
+        "UPDATE expense_category SET parent_id = $2 WHERE parent_id = $1",
+        &[&from_id, &to_id],
+    )?;
+    transaction.execute(
+        "UPDATE budget SET category_id = $2
+         WHERE category_id = $1
+         AND NOT EXISTS (SELECT 1 FROM budget WHERE category_id = $2)",
+        &[&from_id, &to_id],
+    )?;
+    transaction.execute("DELETE FROM budget WHERE category_id = $1", &[&from_id])?;
+    transaction.execute(
+        "DELETE FROM expense_category WHERE category_id = $1",
+        &[&from_id],
+    )?;
+
+    transaction.commit()?;
+
+    Ok((expenses, subscriptions))
+}
+
+/// Delete a category that is not used by any expense or subscription,
+/// together with its budget
+pub fn delete_category(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    category_id: &i32,
+) -> Result<u64, Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+        pool.get().unwrap();
+    let mut transaction = client.transaction()?;
+
+    transaction.execute("DELETE FROM budget WHERE category_id = $1", &[&category_id])?;
+    let rows_deleted = transaction.execute(
+        "DELETE FROM expense_category WHERE category_id = $1",
+        &[&category_id],
+    )?;
+
+    transaction.commit()?;
+
+    Ok(rows_deleted)
+}
+
+pub fn add_income(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    date: &NaiveDate,
+    account_id: &i32,
+    amount: &Decimal,
+    source: &str,
+    note: String,
+) -> Result<(), Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+        pool.get().unwrap();
+
+    let mut transaction = client.transaction()?;
+
+    transaction.execute(
+        "INSERT INTO income (income_id, date, account_id, amount, source, note)
+         VALUES (DEFAULT, $1, $2, $3, $4, $5)",
+        &[&date, &account_id, &amount, &source, &note],
+    )?;
+    adjust_account_value(&mut transaction, account_id, amount, &Some(*date), true)?;
+
+    transaction.commit()?;
+
+    Ok(())
+}
+
+/// Filters for `get_incomes`. Every filter that is set must match.
+#[derive(Debug, Default)]
+pub struct IncomeFilter {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    /// Account name or id
+    pub account: Option<String>,
+    /// Case-insensitive source name
+    pub source: Option<String>,
+    /// Only the latest `limit` matching incomes
+    pub limit: Option<i64>,
+}
+
+impl IncomeFilter {
+    /// Whether no filter other than the limit is set
+    pub fn is_unfiltered(&self) -> bool {
+        self.from.is_none() && self.to.is_none() && self.account.is_none() && self.source.is_none()
+    }
+}
+
+/// Build the WHERE clause and its parameters for an income filter
+fn income_filter_clause(filter: &IncomeFilter) -> (String, Vec<&(dyn ToSql + Sync)>) {
+    let mut clause = WhereClause::default();
+    clause.add(&filter.from, "income.date >= {}");
+    clause.add(&filter.to, "income.date <= {}");
+    clause.add(&filter.account, ACCOUNT_CONDITION);
+    clause.add(&filter.source, "lower(income.source) = lower({})");
+    clause.build()
+}
+
+/// Get the latest incomes matching the filter, oldest first
+pub fn get_incomes(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    filter: &IncomeFilter,
+) -> Result<Vec<Row>, Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+        pool.get().unwrap();
+
+    let (where_clause, mut params) = income_filter_clause(filter);
+    params.push(&filter.limit);
+
+    let q = format!(
+        "WITH t AS (
+            SELECT income.income_id, income.date,
+                   account.account_name, to_char(income.amount, '999999999.00'),
+                   income.source, income.note
+            FROM income
+            LEFT JOIN account
+            ON income.account_id = account.account_id
+            {}
+            ORDER BY income.date DESC, income.income_id DESC
+            LIMIT ${}
+         )
+         SELECT * FROM t ORDER BY date ASC, income_id ASC;",
+        where_clause,
+        params.len()
+    );
+
+    let rows = client.query(q.as_str(), &params)?;
+
+    Ok(rows)
+}
+
+/// Income and expenses per period: period start, income, expenses
+pub fn get_cashflow(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    period: &Period,
+    from: &Option<NaiveDate>,
+    to: &Option<NaiveDate>,
+) -> Result<Vec<Row>, Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+        pool.get().unwrap();
+
+    let rows = client.query(
+        "WITH i AS (
+            SELECT date_trunc($1, date)::date as period, SUM(amount) as income
+            FROM income
+            WHERE ($2::date IS NULL OR date >= $2) AND ($3::date IS NULL OR date <= $3)
+            GROUP BY 1
+         ), e AS (
+            SELECT date_trunc($1, date)::date as period, SUM(amount) as expenses
+            FROM expense
+            WHERE ($2::date IS NULL OR date >= $2) AND ($3::date IS NULL OR date <= $3)
+            GROUP BY 1
+         )
+         SELECT coalesce(i.period, e.period) as period,
+                coalesce(i.income, 0) as income,
+                coalesce(e.expenses, 0) as expenses
+         FROM i
+         FULL JOIN e
+         ON i.period = e.period
+         ORDER BY 1",
+        &[&period.trunc_field(), &from, &to],
+    )?;
+
+    Ok(rows)
+}
+
+/// Record a transfer and move the amount between the two account values,
+/// all in one transaction
+pub fn add_transfer(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    date: &NaiveDate,
+    from_account_id: &i32,
+    to_account_id: &i32,
+    amount: &Decimal,
+    note: String,
+) -> Result<(), Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+        pool.get().unwrap();
+    let mut transaction = client.transaction()?;
+
+    transaction.execute(
+        "INSERT INTO transfer (transfer_id, date, from_account_id, to_account_id, amount, note)
+         VALUES (DEFAULT, $1, $2, $3, $4, $5)",
+        &[&date, &from_account_id, &to_account_id, &amount, &note],
+    )?;
+
+    adjust_account_value(
+        &mut transaction,
+        from_account_id,
+        &-*amount,
+        &Some(*date),
+        false,
+    )?;
+    adjust_account_value(&mut transaction, to_account_id, amount, &Some(*date), false)?;
+
+    transaction.commit()?;
+
+    Ok(())
+}
+
+/// Latest transfers, optionally only those to or from one account, oldest first
+pub fn get_transfers(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    account_id: &Option<i32>,
+    n: &Option<i64>,
+) -> Result<Vec<Row>, Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+        pool.get().unwrap();
+
+    let q = "WITH t AS (
+                SELECT transfer.transfer_id, transfer.date,
+                       from_account.account_name as from_account,
+                       to_account.account_name as to_account,
+                       to_char(transfer.amount, '999999999.00'), transfer.note
+                FROM transfer
+                LEFT JOIN account from_account
+                ON transfer.from_account_id = from_account.account_id
+                LEFT JOIN account to_account
+                ON transfer.to_account_id = to_account.account_id
+                WHERE $1::integer IS NULL
+                OR transfer.from_account_id = $1
+                OR transfer.to_account_id = $1
+                ORDER BY transfer.date DESC, transfer.transfer_id DESC
+                LIMIT $2
+             )
+             SELECT * FROM t ORDER BY date ASC, transfer_id ASC;";
+
+    let rows = client.query(q, &[&account_id, &n])?;
+
+    Ok(rows)
+}
+
+/// Turn balance tracking on or off for an account. Opening values that are
+/// not given keep their previous setting, defaulting to the current value
+/// as of today.
+pub fn set_account_tracking(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    account_id: &i32,
+    track: bool,
+    opening_balance: &Option<Decimal>,
+    opening_date: &Option<NaiveDate>,
+) -> Result<u64, Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+        pool.get().unwrap();
+
+    let rows_updated = client.execute(
+        "UPDATE account
+         SET track_balance = $2,
+             opening_balance = coalesce(
+                 $3,
+                 CASE WHEN opening_date IS NULL
+                      THEN (SELECT account_value FROM account_value
+                            WHERE account_id = $1
+                            ORDER BY recorded_at DESC, account_value_id DESC
+                            LIMIT 1)
+                 END,
+                 opening_balance
+             ),
+             opening_date = coalesce($4, opening_date, CURRENT_DATE)
+         WHERE account_id = $1",
+        &[&account_id, &track, &opening_balance, &opening_date],
+    )?;
+
+    Ok(rows_updated)
+}
+
+/// Accounts that track their balance: account_id, account_name,
+/// opening_balance, opening_date
+pub fn get_tracked_accounts(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+) -> Result<Vec<Row>, Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+        pool.get().unwrap();
+
+    let rows = client.query(
+        "SELECT account_id, account_name, opening_balance, opening_date
+         FROM account
+         WHERE track_balance
+         ORDER BY account_id",
+        &[],
+    )?;
+
+    Ok(rows)
+}
+
+/// Set an account's value to its opening balance plus the income, expenses
+/// and transfers recorded on or after its opening date, returning the value
+pub fn recompute_account_value(
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+    account_id: &i32,
+) -> Result<Decimal, Error> {
+    let mut client: r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager<NoTls>> =
+        pool.get().unwrap();
+
+    let row = client.query_one(
+        "INSERT INTO account_value (account_id, account_value)
+         SELECT account_id,
+                opening_balance
+                + (SELECT coalesce(SUM(amount), 0) FROM income
+                   WHERE income.account_id = account.account_id
+                   AND (account.opening_date IS NULL OR income.date >= account.opening_date))
+                - (SELECT coalesce(SUM(amount), 0) FROM expense
+                   WHERE expense.account_id = account.account_id
+                   AND (account.opening_date IS NULL OR expense.date >= account.opening_date))
+                + (SELECT coalesce(SUM(amount), 0) FROM transfer
+                   WHERE transfer.to_account_id = account.account_id
+                   AND (account.opening_date IS NULL OR transfer.date >= account.opening_date))
+                - (SELECT coalesce(SUM(amount), 0) FROM transfer
+                   WHERE transfer.from_account_id = account.account_id
+                   AND (account.opening_date IS NULL OR transfer.date >= account.opening_date))
+         FROM account
+         WHERE account_id = $1
+         RETURNING account_value",
+        &[&account_id],
+    )?;
+
+    Ok(row.get(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expense_filter_clause_empty() {
+        let filter = ExpenseFilter::default();
+        let (clause, params) = expense_filter_clause(&filter);
+
+        assert_eq!("", clause);
+        assert!(params.is_empty());
+    }
+
+    #[test]
+    fn test_expense_filter_clause_numbering() {
+        let filter = ExpenseFilter {
+            from: Some(NaiveDate::from_ymd(2021, 3, 1)),
+            category: Some("Food".to_string()),
+            max: Some(Decimal::new(50, 0)),
+            search: Some("lunch".to_string()),
+            ..ExpenseFilter::default()
+        };
+        let (clause, params) = expense_filter_clause(&filter);
+
+        assert_eq!(4, params.len());
+        assert!(clause.starts_with("WHERE expense.date >= $1 AND "));
+        assert!(clause.contains("expense.category_id IN (WITH RECURSIVE tree AS"));
+        assert!(clause.contains("lower(category_name) = lower($2)"));
+        assert!(clause.contains("expense.amount <= $3"));
+        assert!(clause.ends_with("expense.note ILIKE '%' || $4 || '%'"));
+    }
+
+    #[test]
+    fn test_income_filter_clause() {
+        let filter = IncomeFilter {
+            to: Some(NaiveDate::from_ymd(2021, 3, 31)),
+            account: Some("Checking".to_string()),
+            source: Some("Employer".to_string()),
+            ..IncomeFilter::default()
+        };
+        let (clause, params) = income_filter_clause(&filter);
+
+        assert_eq!(3, params.len());
+        assert_eq!(
+            "WHERE income.date <= $1 \
+             AND (account.account_id::text = $2 OR lower(account.account_name) = lower($2)) \
+             AND lower(income.source) = lower($3)",
+            clause
+        );
+    }
+}